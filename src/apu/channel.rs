@@ -0,0 +1,94 @@
+//Silences a channel once it has played for the length set in NRx1
+pub struct Length {
+    max: u16,
+    counter: u16,
+    enabled: bool
+}
+
+impl Length {
+    pub fn new(max: u16) -> Length {
+        Length { max, counter: 0, enabled: false }
+    }
+
+    pub fn load(&mut self, length: u8) {
+        self.counter = self.max - length as u16;
+    }
+
+    //Returns whether the counter just ran out
+    pub fn clock(&mut self) -> bool {
+        if !self.enabled || self.counter == 0 {
+            return false;
+        }
+        self.counter -= 1;
+        self.counter == 0
+    }
+
+    //Handles the length enable and trigger bits of an NRx4 write and returns
+    //whether the counter ran out. When the frame sequencer's next step won't
+    //clock lengths, enabling the counter clocks it once straight away, and a
+    //trigger reloading an empty counter loads one less than the maximum.
+    pub fn write_control(&mut self, data: u8, length_next: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = data & 0x40 != 0;
+        let mut expired = false;
+        if !length_next && !was_enabled && self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            expired = self.counter == 0;
+        }
+        if data & 0x80 != 0 && self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && !length_next {
+                self.counter -= 1;
+            }
+        }
+        expired
+    }
+}
+
+//Steps a channel's volume up or down at the rate set in NRx2
+pub struct Envelope {
+    initial: u8,
+    increase: bool,
+    period: u8,
+    timer: u8,
+    pub volume: u8
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope { initial: 0, increase: false, period: 0, timer: 0, volume: 0 }
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.initial = data >> 4;
+        self.increase = data & 0x08 != 0;
+        self.period = data & 0x07;
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    //A period of 0 leaves the volume where it is
+    pub fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.period;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+//The DAC is on while any of the upper five bits of NRx2 are set
+pub fn dac_enabled(nrx2: u8) -> bool {
+    nrx2 & 0xF8 != 0
+}
@@ -0,0 +1,216 @@
+use std::cmp;
+use std::mem;
+
+mod channel;
+mod noise;
+mod pulse;
+mod wave;
+
+use self::noise::Noise;
+use self::pulse::Pulse;
+use self::wave::Wave;
+
+pub const NR10: u16 = 0xFF10;
+const NR14: u16 = 0xFF14;
+//unused, but the base channel 2's registers are numbered from
+const NR20: u16 = 0xFF15;
+const NR24: u16 = 0xFF19;
+const NR30: u16 = 0xFF1A;
+const NR34: u16 = 0xFF1E;
+//unused, like NR20
+const NR40: u16 = 0xFF1F;
+const NR44: u16 = 0xFF23;
+const NR50: u16 = 0xFF24;
+const NR51: u16 = 0xFF25;
+const NR52: u16 = 0xFF26;
+const WAVE_START: u16 = 0xFF30;
+pub const WAVE_END: u16 = 0xFF3F;
+
+//Bits that read back as 1 in NR10-NR51, which covers the write-only ones
+const READ_MASKS: [u8; 0x16] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,
+    0x00, 0x00
+];
+
+const NR52_POWER: u8 = 0x80;
+
+//Bit of the timer's internal counter, DIV bit 4, whose falling edge steps
+//the frame sequencer at 512 Hz. Double speed uses the next bit up.
+pub const SEQUENCER_BIT: u16 = 1 << 12;
+
+pub const SAMPLE_RATE: u32 = 48_000;
+const CLOCK_HZ: u32 = 4_194_304;
+//How much of the output's DC offset the high-pass filter keeps per sample,
+//modelling the capacitor on the real output
+const CHARGE_FACTOR: f32 = 0.996;
+
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    wave: Wave,
+    noise: Noise,
+    //NR10-NR51 as last written
+    registers: [u8; 0x16],
+    powered: bool,
+    //the frame sequencer step to run next
+    step: u8,
+
+    //SAMPLE_RATE added per dot, a sample is taken each time it passes CLOCK_HZ
+    sample_clock: u32,
+    capacitors: [f32; 2],
+    //interleaved left and right samples not yet taken
+    samples: Vec<f32>
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            registers: [0; 0x16],
+            powered: false,
+            step: 0,
+            sample_clock: 0,
+            capacitors: [0.0; 2],
+            samples: Vec::new()
+        }
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            NR10..=NR51 => {
+                let index = (addr - NR10) as usize;
+                self.registers[index] | READ_MASKS[index]
+            }
+            NR52 => {
+                let status = [self.pulse1.enabled, self.pulse2.enabled, self.wave.enabled, self.noise.enabled];
+                let channels = status.iter().enumerate()
+                    .fold(0, |bits, (i, &enabled)| bits | (enabled as u8) << i);
+                (self.powered as u8) << 7 | 0x70 | channels
+            }
+            WAVE_START..=WAVE_END => self.wave.read_ram(addr - WAVE_START),
+            _ => 0xFF
+        }
+    }
+
+    //While powered off only NR52 and wave RAM can be written
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            NR52 => self.set_power(data & NR52_POWER != 0),
+            WAVE_START..=WAVE_END => self.wave.write_ram(addr - WAVE_START, data),
+            _ if !self.powered => {}
+            NR10..=NR51 => {
+                self.registers[(addr - NR10) as usize] = data;
+                //whether the frame sequencer's next step clocks lengths
+                let length_next = self.step.is_multiple_of(2);
+                match addr {
+                    NR10..=NR14 => self.pulse1.write(addr - NR10, data, length_next),
+                    NR20..=NR24 => self.pulse2.write(addr - NR20, data, length_next),
+                    NR30..=NR34 => self.wave.write(addr - NR30, data, length_next),
+                    NR40..=NR44 => self.noise.write(addr - NR40, data, length_next),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Powering off clears every register, powering on restarts the frame sequencer
+    fn set_power(&mut self, on: bool) {
+        if self.powered && !on {
+            self.pulse1 = Pulse::new(true);
+            self.pulse2 = Pulse::new(false);
+            self.wave.power_off();
+            self.noise = Noise::new();
+            self.registers = [0; 0x16];
+        } else if !self.powered && on {
+            self.step = 0;
+        }
+        self.powered = on;
+    }
+
+    //Runs one of the frame sequencer's eight steps, clocking lengths on
+    //even steps, the sweep on steps 2 and 6 and envelopes on step 7
+    pub fn step_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        if self.step.is_multiple_of(2) {
+            self.pulse1.clock_length();
+            self.pulse2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+        if self.step == 2 || self.step == 6 {
+            self.pulse1.clock_sweep();
+        }
+        if self.step == 7 {
+            self.pulse1.clock_envelope();
+            self.pulse2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+        self.step = (self.step + 1) % 8;
+    }
+
+    //Advances the channels by `dots` and takes any output samples that fall due
+    pub fn tick(&mut self, dots: u32) {
+        let mut dots = dots;
+        while dots > 0 {
+            let until_sample = (CLOCK_HZ - self.sample_clock).div_ceil(SAMPLE_RATE);
+            let run = cmp::min(dots, until_sample);
+            if self.powered {
+                self.pulse1.tick(run);
+                self.pulse2.tick(run);
+                self.wave.tick(run);
+                self.noise.tick(run);
+            }
+            dots -= run;
+            self.sample_clock += run * SAMPLE_RATE;
+            if self.sample_clock >= CLOCK_HZ {
+                self.sample_clock -= CLOCK_HZ;
+                self.mix();
+            }
+        }
+    }
+
+    //Hands over the samples produced since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::take(&mut self.samples)
+    }
+
+    //Sums the channels routed to each side through NR51 and scales by the
+    //NR50 master volume. Each DAC maps digital 0 to 15 onto 1.0 to -1.0.
+    fn mix(&mut self) {
+        let mut sides = [0.0f32; 2];
+        if self.powered {
+            let outputs = [self.pulse1.output(), self.pulse2.output(), self.wave.output(), self.noise.output()];
+            let nr50 = self.registers[(NR50 - NR10) as usize];
+            let nr51 = self.registers[(NR51 - NR10) as usize];
+            for (i, output) in outputs.iter().enumerate() {
+                if let Some(level) = *output {
+                    let analog = 1.0 - level as f32 / 7.5;
+                    if nr51 & (0x10 << i) != 0 {
+                        sides[0] += analog;
+                    }
+                    if nr51 & (0x01 << i) != 0 {
+                        sides[1] += analog;
+                    }
+                }
+            }
+            let volumes = [(nr50 >> 4) & 0x07, nr50 & 0x07];
+            for (side, &volume) in sides.iter_mut().zip(volumes.iter()) {
+                *side *= (volume + 1) as f32 / 8.0 / 4.0;
+            }
+        }
+        for (side, capacitor) in sides.iter().zip(self.capacitors.iter_mut()) {
+            let out = side - *capacitor;
+            *capacitor = side - out * CHARGE_FACTOR;
+            self.samples.push(out);
+        }
+    }
+}
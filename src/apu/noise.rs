@@ -0,0 +1,113 @@
+use super::channel::{dac_enabled, Envelope, Length};
+
+//Dots between LFSR shifts for each NR43 divisor code, before the clock shift
+const DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+//Channel 4, which outputs the low bit of a linear feedback shift register
+pub struct Noise {
+    pub enabled: bool,
+    dac: bool,
+    shift: u8,
+    //a 7-bit rather than 15-bit LFSR, for a more tonal sound
+    narrow: bool,
+    divisor: u8,
+    //dots until the next shift
+    timer: u32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope
+}
+
+impl Noise {
+    pub fn new() -> Noise {
+        Noise {
+            enabled: false,
+            dac: false,
+            shift: 0,
+            narrow: false,
+            divisor: 0,
+            timer: DIVISORS[0],
+            lfsr: 0x7FFF,
+            length: Length::new(64),
+            envelope: Envelope::new()
+        }
+    }
+
+    //Takes a write to NR41-NR44
+    pub fn write(&mut self, register: u16, data: u8, length_next: bool) {
+        match register {
+            1 => self.length.load(data & 0x3F),
+            2 => {
+                self.envelope.write(data);
+                self.dac = dac_enabled(data);
+                self.enabled &= self.dac;
+            }
+            3 => {
+                self.shift = data >> 4;
+                self.narrow = data & 0x08 != 0;
+                self.divisor = data & 0x07;
+            }
+            4 => {
+                let expired = self.length.write_control(data, length_next);
+                if data & 0x80 != 0 {
+                    self.trigger();
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.timer = self.period();
+        self.lfsr = 0x7FFF;
+        self.envelope.trigger();
+    }
+
+    fn period(&self) -> u32 {
+        DIVISORS[self.divisor as usize] << self.shift
+    }
+
+    pub fn tick(&mut self, dots: u32) {
+        let mut dots = dots;
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = self.period();
+            //clock shifts of 14 and 15 stop the LFSR
+            if self.shift < 14 {
+                self.step_lfsr();
+            }
+        }
+        self.timer -= dots;
+    }
+
+    //Shifts right, feeding the XOR of the two low bits into bit 14, and into
+    //bit 6 as well in 7-bit mode
+    fn step_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.narrow {
+            self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    //Digital level from 0 to 15, or None with the DAC off
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        Some(if self.enabled && self.lfsr & 1 == 0 { self.envelope.volume } else { 0 })
+    }
+}
@@ -0,0 +1,182 @@
+use super::channel::{dac_enabled, Envelope, Length};
+
+//Output level at each of the eight steps of the 12.5%, 25%, 50% and 75% duty cycles
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const MAX_FREQUENCY: u16 = 2047;
+
+//Channel 1's frequency sweep
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    //frequency the sweep works from, copied on trigger
+    shadow: u16,
+    enabled: bool,
+    //a subtraction has been calculated since the last trigger
+    negated: bool
+}
+
+//Square wave channels 1 and 2
+pub struct Pulse {
+    pub enabled: bool,
+    dac: bool,
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    //dots until the next duty step
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    sweep: Option<Sweep>
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep { period: 0, negate: false, shift: 0, timer: 0, shadow: 0, enabled: false, negated: false }
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    //Timer periods of 0 count as 8
+    fn reload(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+impl Pulse {
+    pub fn new(sweep: bool) -> Pulse {
+        Pulse {
+            enabled: false,
+            dac: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: period(0),
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep: if sweep { Some(Sweep::new()) } else { None }
+        }
+    }
+
+    //Takes a write to NRx0-NRx4
+    pub fn write(&mut self, register: u16, data: u8, length_next: bool) {
+        match register {
+            0 => {
+                if let Some(ref mut sweep) = self.sweep {
+                    //leaving negate mode after a subtraction silences the channel
+                    if sweep.negate && data & 0x08 == 0 && sweep.negated {
+                        self.enabled = false;
+                    }
+                    sweep.period = (data >> 4) & 0x07;
+                    sweep.negate = data & 0x08 != 0;
+                    sweep.shift = data & 0x07;
+                }
+            }
+            1 => {
+                self.duty = data >> 6;
+                self.length.load(data & 0x3F);
+            }
+            2 => {
+                self.envelope.write(data);
+                self.dac = dac_enabled(data);
+                self.enabled &= self.dac;
+            }
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((data & 0x07) as u16) << 8;
+                let expired = self.length.write_control(data, length_next);
+                if data & 0x80 != 0 {
+                    self.trigger();
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.timer = period(self.frequency);
+        self.envelope.trigger();
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.shadow = self.frequency;
+            sweep.reload();
+            sweep.enabled = sweep.period != 0 || sweep.shift != 0;
+            sweep.negated = false;
+            //the overflow check runs straight away, though nothing is written back
+            if sweep.shift != 0 && sweep.calculate() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn tick(&mut self, dots: u32) {
+        let mut dots = dots;
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = period(self.frequency);
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+        self.timer -= dots;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let sweep = match self.sweep {
+            Some(ref mut sweep) => sweep,
+            None => return
+        };
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload();
+        if !sweep.enabled || sweep.period == 0 {
+            return;
+        }
+        let frequency = sweep.calculate();
+        if frequency > MAX_FREQUENCY {
+            self.enabled = false;
+        } else if sweep.shift != 0 {
+            sweep.shadow = frequency;
+            self.frequency = frequency;
+            //the new frequency is checked for overflow again but not applied
+            if sweep.calculate() > MAX_FREQUENCY {
+                self.enabled = false;
+            }
+        }
+    }
+
+    //Digital level from 0 to 15, or None with the DAC off
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step) & 1 != 0;
+        Some(if self.enabled && high { self.envelope.volume } else { 0 })
+    }
+}
+
+//Dots per duty step
+fn period(frequency: u16) -> u32 {
+    (2048 - frequency as u32) * 4
+}
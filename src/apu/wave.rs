@@ -0,0 +1,115 @@
+use super::channel::Length;
+
+const RAM_SIZE: usize = 16;
+//Right shift applied to samples for each NR32 output level: mute, 100%, 50% and 25%
+const VOLUME_SHIFTS: [u8; 4] = [4, 0, 1, 2];
+
+//Channel 3, which plays back the 32 four-bit samples held in wave RAM
+pub struct Wave {
+    pub enabled: bool,
+    dac: bool,
+    volume: u8,
+    frequency: u16,
+    //dots until the next sample
+    timer: u32,
+    position: u8,
+    //the sample last read from wave RAM
+    sample: u8,
+    length: Length,
+    ram: [u8; RAM_SIZE]
+}
+
+impl Wave {
+    pub fn new() -> Wave {
+        Wave {
+            enabled: false,
+            dac: false,
+            volume: 0,
+            frequency: 0,
+            timer: period(0),
+            position: 0,
+            sample: 0,
+            length: Length::new(256),
+            ram: [0; RAM_SIZE]
+        }
+    }
+
+    //Turning the APU off clears the registers but not wave RAM
+    pub fn power_off(&mut self) {
+        let ram = self.ram;
+        *self = Wave::new();
+        self.ram = ram;
+    }
+
+    pub fn read_ram(&self, index: u16) -> u8 {
+        self.ram[index as usize]
+    }
+
+    pub fn write_ram(&mut self, index: u16, data: u8) {
+        self.ram[index as usize] = data;
+    }
+
+    //Takes a write to NR30-NR34
+    pub fn write(&mut self, register: u16, data: u8, length_next: bool) {
+        match register {
+            0 => {
+                self.dac = data & 0x80 != 0;
+                self.enabled &= self.dac;
+            }
+            1 => self.length.load(data),
+            2 => self.volume = (data >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x700) | data as u16,
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | ((data & 0x07) as u16) << 8;
+                let expired = self.length.write_control(data, length_next);
+                if data & 0x80 != 0 {
+                    self.trigger();
+                } else if expired {
+                    self.enabled = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Playback restarts at the first sample, but the buffer isn't refilled so
+    //the last sample read plays until the position next advances
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.timer = period(self.frequency);
+        self.position = 0;
+    }
+
+    pub fn tick(&mut self, dots: u32) {
+        let mut dots = dots;
+        while dots >= self.timer {
+            dots -= self.timer;
+            self.timer = period(self.frequency);
+            if self.enabled {
+                self.position = (self.position + 1) % 32;
+                let byte = self.ram[self.position as usize / 2];
+                self.sample = if self.position.is_multiple_of(2) { byte >> 4 } else { byte & 0x0F };
+            }
+        }
+        self.timer -= dots;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    //Digital level from 0 to 15, or None with the DAC off
+    pub fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        Some(if self.enabled { self.sample >> VOLUME_SHIFTS[self.volume as usize] } else { 0 })
+    }
+}
+
+//Dots per sample
+fn period(frequency: u16) -> u32 {
+    (2048 - frequency as u32) * 2
+}
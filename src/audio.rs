@@ -0,0 +1,51 @@
+extern crate sdl2;
+
+use self::sdl2::audio::{AudioQueue, AudioSpecDesired};
+
+use std::mem;
+use std::thread;
+use std::time::Duration;
+
+use apu::SAMPLE_RATE;
+
+//Stereo samples queued that audio sync waits to drain down to, about 50 ms
+const TARGET_QUEUED: u32 = SAMPLE_RATE / 20;
+//Past about 200 ms queued, new samples are dropped rather than adding latency
+const MAX_QUEUED: u32 = SAMPLE_RATE / 5;
+
+//Plays the APU's output through an SDL audio queue
+pub struct Audio {
+    queue: AudioQueue<f32>
+}
+
+impl Audio {
+    pub fn new(sdl_context: &sdl2::Sdl) -> Result<Audio, String> {
+        let audio_subsys = sdl_context.audio()?;
+        let spec = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE as i32),
+            channels: Some(2),
+            samples: Some(1024)
+        };
+        let queue = audio_subsys.open_queue(None, &spec)?;
+        queue.resume();
+        Ok(Audio { queue })
+    }
+
+    pub fn play(&mut self, samples: &[f32]) {
+        if self.queued() < MAX_QUEUED {
+            self.queue.queue(samples);
+        }
+    }
+
+    //Blocks until the queue is down to its target length, pacing emulation
+    //to the sound card's clock
+    pub fn wait(&self) {
+        while self.queued() > TARGET_QUEUED {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn queued(&self) -> u32 {
+        self.queue.size() / (2 * mem::size_of::<f32>() as u32)
+    }
+}
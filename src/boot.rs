@@ -0,0 +1,199 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+//Size of the DMG, MGB and SGB boot ROMs, and of the CGB one, which also
+//covers 0x0200-0x08FF and leaves the cartridge header visible in between
+const SMALL_SIZE: usize = 0x100;
+const CGB_SIZE: usize = 0x900;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb
+}
+
+pub struct BootRom {
+    data: Box<[u8]>
+}
+
+#[derive(Debug)]
+pub enum BootRomError {
+    Io(io::Error),
+    WrongSize { model: Model, expected: usize, actual: usize }
+}
+
+//The DMG boot ROM, used when no boot ROM file is given
+const DMG_BOOT_ROM: [u8; 256] = [
+	0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
+    0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
+    0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A, 0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
+    0xFE, 0x34, 0x20, 0xF3, 0x11, 0xD8, 0x00, 0x06, 0x08, 0x1A, 0x13, 0x22, 0x23, 0x05, 0x20, 0xF9,
+    0x3E, 0x19, 0xEA, 0x10, 0x99, 0x21, 0x2F, 0x99, 0x0E, 0x0C, 0x3D, 0x28, 0x08, 0x32, 0x0D, 0x20,
+    0xF9, 0x2E, 0x0F, 0x18, 0xF3, 0x67, 0x3E, 0x64, 0x57, 0xE0, 0x42, 0x3E, 0x91, 0xE0, 0x40, 0x04,
+    0x1E, 0x02, 0x0E, 0x0C, 0xF0, 0x44, 0xFE, 0x90, 0x20, 0xFA, 0x0D, 0x20, 0xF7, 0x1D, 0x20, 0xF2,
+    0x0E, 0x13, 0x24, 0x7C, 0x1E, 0x83, 0xFE, 0x62, 0x28, 0x06, 0x1E, 0xC1, 0xFE, 0x64, 0x20, 0x06,
+    0x7B, 0xE2, 0x0C, 0x3E, 0x87, 0xE2, 0xF0, 0x42, 0x90, 0xE0, 0x42, 0x15, 0x20, 0xD2, 0x05, 0x20,
+    0x4F, 0x16, 0x20, 0x18, 0xCB, 0x4F, 0x06, 0x04, 0xC5, 0xCB, 0x11, 0x17, 0xC1, 0xCB, 0x11, 0x17,
+    0x05, 0x20, 0xF5, 0x22, 0x23, 0x22, 0x23, 0xC9, 0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B,
+    0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
+    0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E, 0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C,
+    0x21, 0x04, 0x01, 0x11, 0xA8, 0x00, 0x1A, 0x13, 0xBE, 0x00, 0x00, 0x23, 0x7D, 0xFE, 0x34, 0x20,
+	0xF5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xFB, 0x86, 0x00, 0x00, 0x3E, 0x01, 0xE0, 0x50
+];
+
+//I/O registers as the DMG and MGB boot ROMs leave them. NR52 goes first
+//since the other sound registers ignore writes while the APU is off.
+const DMG_IO: [(u16, u8); 30] = [
+    (0xFF00, 0xCF), (0xFF01, 0x00), (0xFF02, 0x7E), (0xFF05, 0x00), (0xFF06, 0x00),
+    (0xFF07, 0xF8), (0xFF0F, 0xE1),
+    (0xFF26, 0xF1), (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF),
+    (0xFF14, 0xBF), (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+    (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+    (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF), (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF40, 0x91), (0xFF47, 0xFC)
+];
+
+//Internal timer counter on reaching 0x0100. It depends on how long the boot
+//ROM ran, which on SGB varies with the header, so there it's approximate.
+pub const POST_BOOT_DIVIDER: u16 = 0xABCC;
+
+//Where the SGB boot ROM leaves a register differently. It plays no startup
+//sound, so channel 1 is never triggered and NR52 reads 0xF0.
+const SGB_IO: [(u16, u8); 1] = [(0xFF14, 0x3F)];
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        self == Model::Cgb
+    }
+
+    fn boot_rom_size(self) -> usize {
+        if self.is_cgb() { CGB_SIZE } else { SMALL_SIZE }
+    }
+
+    //Skipping the boot ROM only works for the monochrome models. On CGB it
+    //would tell dual-mode games they have CGB hardware, whose banking and
+    //palettes aren't emulated, so that needs the real boot ROM.
+    pub fn can_skip_boot(self) -> bool {
+        !self.is_cgb()
+    }
+
+    //AF, BC, DE and HL as the boot ROM leaves them. The DMG and MGB ROMs
+    //leave H and C set unless the header checksum byte is zero.
+    pub fn post_boot_registers(self, header_checksum: u8) -> [u16; 4] {
+        let flags = if header_checksum == 0 { 0x80 } else { 0xB0 };
+        match self {
+            Model::Dmg => [0x0100 | flags, 0x0013, 0x00D8, 0x014D],
+            Model::Mgb => [0xFF00 | flags, 0x0013, 0x00D8, 0x014D],
+            Model::Sgb => [0x0100, 0x0014, 0x0000, 0xC060],
+            Model::Cgb => unreachable!("the CGB boot ROM can't be skipped")
+        }
+    }
+
+    //I/O register writes that reproduce the post-boot hardware state
+    pub fn post_boot_io(self) -> Vec<(u16, u8)> {
+        let overrides: &[(u16, u8)] = match self {
+            Model::Sgb => &SGB_IO,
+            _ => &[]
+        };
+        DMG_IO.iter().map(|&(addr, data)| {
+            match overrides.iter().find(|&&(overridden, _)| overridden == addr) {
+                Some(&register) => register,
+                None => (addr, data)
+            }
+        }).collect()
+    }
+
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Model, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            _ => Err(format!("unknown model: {}", s))
+        }
+    }
+}
+
+impl BootRom {
+    pub fn dmg() -> BootRom {
+        BootRom { data: Box::new(DMG_BOOT_ROM) }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P, model: Model) -> Result<BootRom, BootRomError> {
+        let data = fs::read(path)?;
+        let expected = model.boot_rom_size();
+        if data.len() != expected {
+            return Err(BootRomError::WrongSize { model, expected, actual: data.len() });
+        }
+        Ok(BootRom { data: data.into_boxed_slice() })
+    }
+
+    //The byte the boot ROM overlays at `addr`, or None where the cartridge shows through
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr as usize {
+            addr @ 0x0000..=0x00FF => Some(self.data[addr]),
+            addr @ 0x0200..=0x08FF if self.data.len() == CGB_SIZE => Some(self.data[addr]),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for BootRomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootRomError::Io(ref err) => write!(f, "could not read boot ROM: {}", err),
+            BootRomError::WrongSize { model, expected, actual } =>
+                write!(f, "a {:?} boot ROM is {} bytes, found {}", model, expected, actual)
+        }
+    }
+}
+
+impl error::Error for BootRomError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            BootRomError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for BootRomError {
+    fn from(err: io::Error) -> BootRomError {
+        BootRomError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sgb_overrides_replace_dmg_registers() {
+        let dmg = Model::Dmg.post_boot_io();
+        let sgb = Model::Sgb.post_boot_io();
+        assert_eq!(dmg.len(), sgb.len());
+        let differences: Vec<_> = dmg.iter().zip(sgb.iter()).filter(|&(d, s)| d != s).map(|(_, &s)| s).collect();
+        assert_eq!(differences, SGB_IO);
+        assert_eq!(Model::Mgb.post_boot_io(), dmg);
+    }
+
+    #[test]
+    fn only_cgb_needs_its_boot_rom() {
+        assert!(Model::Dmg.can_skip_boot());
+        assert!(Model::Sgb.can_skip_boot());
+        assert!(!Model::Cgb.can_skip_boot());
+    }
+}
@@ -0,0 +1,318 @@
+use boot;
+use boot::{BootRom, Model};
+use cartridge::Cartridge;
+use interrupt;
+use interrupt::Interrupt;
+use joypad;
+use joypad::Button;
+use ppu;
+use timer;
+use apu;
+
+const WRAM_START: u16 = 0xC000;
+const ECHO_START: u16 = 0xE000;
+const IO_START: u16 = 0xFF00;
+const HRAM_START: u16 = 0xFF80;
+
+//CGB speed switch register, armed by setting bit 0 before STOP
+const KEY1: u16 = 0xFF4D;
+//Writing XX starts copying XX00-XX9F to OAM
+const DMA: u16 = 0xFF46;
+const DMA_LENGTH: u16 = 0xA0;
+//Writing a non-zero value unmaps the boot ROM until the next reset
+const BOOT: u16 = 0xFF50;
+
+//Routes every CPU access to the component mapped at that address
+pub struct Bus {
+    pub model: Model,
+    pub cartridge: Cartridge,
+    //overlays the start of the cartridge until the boot sequence writes BOOT
+    boot_rom: Option<BootRom>,
+    wram: Box<[u8]>,
+    //I/O registers without a dedicated handler are plain storage for now
+    io: [u8; 0x80],
+    hram: [u8; 0x7F],
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    //the PPU and cartridge clock don't speed up with the CPU
+    double_speed: bool,
+    timer: timer::Timer,
+    joypad: joypad::Joypad,
+    //a selected P1 line fell since the CPU last checked, which wakes it from STOP
+    joypad_edge: bool,
+    pub ppu: ppu::Ppu,
+    pub apu: apu::Apu,
+    dma: Option<Dma>
+}
+
+//An OAM DMA transfer in progress, copying one byte per M-cycle
+struct Dma {
+    source: u16,
+    //M-cycles since the write to 0xFF46. The first goes on setting up,
+    //before the bus is taken, and each one after that copies a byte.
+    cycles: u16
+}
+
+impl Bus {
+
+    pub fn new(model: Model, cartridge: Cartridge, boot_rom: Option<BootRom>) -> Bus {
+        Bus {
+            model,
+            cartridge,
+            boot_rom,
+            wram: vec![0; 0x2000].into_boxed_slice(),
+            io: [0; 0x80],
+            hram: [0; 0x7F],
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            double_speed: false,
+            timer: timer::Timer::new(),
+            joypad: joypad::Joypad::new(),
+            joypad_edge: false,
+            ppu: ppu::Ppu::new(),
+            apu: apu::Apu::new(),
+            dma: None
+        }
+    }
+
+    //CPU reads of memory it is locked out of return 0xFF
+    pub fn read8(&self, addr:u16) -> u8 {
+        if !self.accessible(addr) {
+            return 0xFF;
+        }
+        self.read_mapped(addr)
+    }
+
+    //and writes to it are dropped
+    pub fn write8(&mut self, addr:u16, data:u8) {
+        if !self.accessible(addr) {
+            return;
+        }
+        self.write_mapped(addr, data);
+    }
+
+    //While OAM DMA runs the CPU can only reach HRAM and the registers on its
+    //own side of the bus. Otherwise the PPU holds VRAM during pixel transfer
+    //and OAM from OAM scan onwards.
+    fn accessible(&self, addr:u16) -> bool {
+        if self.dma.as_ref().is_some_and(|dma| dma.cycles > 1) {
+            return addr >= IO_START;
+        }
+        match addr {
+            0x8000..=0x9FFF => self.ppu.vram_accessible(),
+            0xFE00..=0xFE9F => self.ppu.oam_accessible(),
+            _ => true
+        }
+    }
+
+    fn read_mapped(&self, addr:u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => {
+                match self.boot_rom.as_ref().and_then(|boot_rom| boot_rom.read(addr)) {
+                    Some(data) => data,
+                    None => self.cartridge.read_rom(addr)
+                }
+            }
+            0x8000..=0x9FFF => {self.ppu.read_vram(addr)}
+            0xA000..=0xBFFF => {self.cartridge.read_ram(addr)}
+            0xC000..=0xDFFF => {self.wram[(addr - WRAM_START) as usize]}
+            //echo RAM mirrors 0xC000-0xDDFF
+            0xE000..=0xFDFF => {self.wram[(addr - ECHO_START) as usize]}
+            0xFE00..=0xFE9F => {self.ppu.read_oam(addr)}
+            //unusable, reads 0x00 on DMG
+            0xFEA0..=0xFEFF => {0x00}
+            0xFF00..=0xFF7F => {self.read_io(addr)}
+            0xFF80..=0xFFFE => {self.hram[(addr - HRAM_START) as usize]}
+            interrupt::IE => {self.interrupt_enable}
+        }
+    }
+
+    fn write_mapped(&mut self, addr:u16, data:u8) {
+        match addr {
+            0x0000..=0x7FFF => {self.cartridge.write_rom(addr, data)}
+            0x8000..=0x9FFF => {self.ppu.write_vram(addr, data)}
+            0xA000..=0xBFFF => {self.cartridge.write_ram(addr, data)}
+            0xC000..=0xDFFF => {self.wram[(addr - WRAM_START) as usize] = data}
+            0xE000..=0xFDFF => {self.wram[(addr - ECHO_START) as usize] = data}
+            0xFE00..=0xFE9F => {self.ppu.write_oam(addr, data)}
+            0xFEA0..=0xFEFF => {}
+            0xFF00..=0xFF7F => {self.write_io(addr, data)}
+            0xFF80..=0xFFFE => {self.hram[(addr - HRAM_START) as usize] = data}
+            interrupt::IE => {self.interrupt_enable = data}
+        }
+    }
+
+    fn read_io(&self, addr:u16) -> u8 {
+        match addr {
+            joypad::P1 => {self.joypad.read()}
+            timer::DIV..=timer::TAC => {self.timer.read(addr)}
+            ppu::LCDC..=ppu::LYC | ppu::BGP..=ppu::WX => {self.ppu.read(addr)}
+            apu::NR10..=apu::WAVE_END => {self.apu.read(addr)}
+            //the top three bits of IF are unused and always read high
+            interrupt::IF => {self.interrupt_flag | 0xE0}
+            KEY1 if self.model.is_cgb() => {self.io[(KEY1 - IO_START) as usize] | 0x7E}
+            KEY1 | BOOT => {0xFF}
+            _ => {self.io[(addr - IO_START) as usize]}
+        }
+    }
+
+    fn write_io(&mut self, addr:u16, data:u8) {
+        match addr {
+            joypad::P1 => {
+                if self.joypad.write(data) {
+                    self.joypad_edge = true;
+                    self.request_interrupt(Interrupt::Joypad);
+                }
+            }
+            timer::DIV..=timer::TAC => {
+                let counter = self.timer.counter();
+                self.timer.write(addr, data);
+                self.clock_sequencer(counter);
+            }
+            ppu::LCDC..=ppu::LYC | ppu::BGP..=ppu::WX => {self.ppu.write(addr, data)}
+            apu::NR10..=apu::WAVE_END => {self.apu.write(addr, data)}
+            interrupt::IF => {self.interrupt_flag = data & 0x1F}
+            //only the armed bit is writable, the current speed is read-only
+            KEY1 if self.model.is_cgb() => {
+                let key1 = &mut self.io[(KEY1 - IO_START) as usize];
+                *key1 = (*key1 & 0x80) | (data & 0x01);
+            }
+            KEY1 => {}
+            DMA => {
+                self.io[(DMA - IO_START) as usize] = data;
+                self.dma = Some(Dma { source: (data as u16) << 8, cycles: 0 });
+            }
+            BOOT => {
+                if data != 0 {
+                    self.boot_rom = None;
+                }
+            }
+            _ => {self.io[(addr - IO_START) as usize] = data}
+        }
+    }
+
+    //Puts the hardware in the state the boot ROM would have left it in
+    pub fn skip_boot(&mut self) {
+        self.boot_rom = None;
+        for &(addr, data) in &self.model.post_boot_io() {
+            self.write8(addr, data);
+        }
+        self.timer.set_counter(boot::POST_BOOT_DIVIDER);
+    }
+
+    //A STOP now would switch CPU speed rather than enter low-power mode
+    pub fn speed_switch_armed(&self) -> bool {
+        self.model.is_cgb() && self.io[(KEY1 - IO_START) as usize] & 0x01 != 0
+    }
+
+    //Completes a speed switch, disarming KEY1 and reporting the new speed in bit 7
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.io[(KEY1 - IO_START) as usize] = (double_speed as u8) << 7;
+        self.double_speed = double_speed;
+    }
+
+    //Advances the hardware clocked alongside the CPU by `cycles` T-cycles
+    pub fn tick(&mut self, cycles:u32) {
+        let counter = self.timer.counter();
+        if self.timer.tick(cycles) {
+            self.request_interrupt(Interrupt::Timer);
+        }
+        self.clock_sequencer(counter);
+        for _ in 0..cycles / 4 {
+            self.step_dma();
+        }
+        let dots = if self.double_speed { cycles / 2 } else { cycles };
+        self.interrupt_flag |= self.ppu.tick(dots);
+        self.apu.tick(dots);
+        self.cartridge.tick(dots);
+    }
+
+    //Steps the APU's frame sequencer if its DIV bit fell since the timer's
+    //counter was `before`, which a DIV reset can also cause
+    fn clock_sequencer(&mut self, before: u16) {
+        let bit = if self.double_speed { apu::SEQUENCER_BIT << 1 } else { apu::SEQUENCER_BIT };
+        if before & bit != 0 && self.timer.counter() & bit == 0 {
+            self.apu.step_sequencer();
+        }
+    }
+
+    fn step_dma(&mut self) {
+        let (source, index) = match self.dma {
+            Some(ref mut dma) => {
+                dma.cycles += 1;
+                (dma.source, dma.cycles.wrapping_sub(2))
+            }
+            None => return
+        };
+        if index == DMA_LENGTH {
+            self.dma = None;
+        } else if index < DMA_LENGTH {
+            //sources past 0xDFFF read echo RAM rather than OAM and I/O
+            let addr = source + index;
+            let addr = if addr >= ECHO_START { addr - 0x2000 } else { addr };
+            let data = self.read_mapped(addr);
+            self.ppu.write_oam(0xFE00 + index, data);
+        }
+    }
+
+    pub fn press(&mut self, button: Button) {
+        if self.joypad.press(button) {
+            self.joypad_edge = true;
+            self.request_interrupt(Interrupt::Joypad);
+        }
+    }
+
+    //Returns whether a selected P1 line has fallen since the last call
+    pub fn take_joypad_edge(&mut self) -> bool {
+        let edge = self.joypad_edge;
+        self.joypad_edge = false;
+        edge
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.joypad.release(button);
+    }
+
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag |= interrupt.bit();
+    }
+
+    pub fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupt_flag &= !interrupt.bit();
+    }
+
+    //Interrupts that are both requested and enabled
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_flag & self.interrupt_enable & 0x1F
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus() -> Bus {
+        let mut rom = vec![0; 0x8000];
+        rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        Bus::new(Model::Dmg, Cartridge::from_bytes(&rom).unwrap(), None)
+    }
+
+    #[test]
+    fn oam_dma_starts_an_m_cycle_after_the_write() {
+        let mut bus = bus();
+        bus.write8(0xC000, 0x12);
+        bus.write8(0xC09F, 0x34);
+        bus.write8(DMA, 0xC0);
+        bus.tick(4);
+        assert_eq!(bus.ppu.read_oam(0xFE00), 0x00);
+        assert_eq!(bus.read8(0xC000), 0x12);
+        bus.tick(4);
+        assert_eq!(bus.ppu.read_oam(0xFE00), 0x12);
+        assert_eq!(bus.read8(0xC000), 0xFF);
+        bus.tick(4 * (DMA_LENGTH as u32 - 1));
+        assert_eq!(bus.ppu.read_oam(0xFE9F), 0x34);
+        assert_eq!(bus.read8(0xC000), 0xFF);
+        bus.tick(4);
+        assert_eq!(bus.read8(0xC000), 0x12);
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use super::CartridgeError;
+
+//The header occupies 0x0100-0x014F, after the RST and interrupt vectors
+pub const HEADER_END: usize = 0x0150;
+
+const TITLE: usize = 0x0134;
+const MANUFACTURER: usize = 0x013F;
+const CGB_FLAG: usize = 0x0143;
+const NEW_LICENSEE: usize = 0x0144;
+const SGB_FLAG: usize = 0x0146;
+const CARTRIDGE_TYPE: usize = 0x0147;
+const ROM_SIZE: usize = 0x0148;
+const RAM_SIZE: usize = 0x0149;
+const DESTINATION: usize = 0x014A;
+const OLD_LICENSEE: usize = 0x014B;
+const VERSION: usize = 0x014C;
+const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM: usize = 0x014E;
+
+//Old licensee code meaning "see the new licensee code instead"
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CgbSupport {
+    None,
+    //enhanced for CGB but still runs on DMG
+    Compatible,
+    Only
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Destination {
+    Japan,
+    Overseas
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1
+}
+
+//What the cartridge type byte at 0x0147 says is on the board
+#[derive(Clone, Copy, Debug)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mbc: Mbc,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool
+}
+
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub old_licensee: u8,
+    //only meaningful when the old licensee code is 0x33
+    pub new_licensee: Option<String>,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16
+}
+
+impl CartridgeType {
+    fn parse(code: u8) -> Option<CartridgeType> {
+        //(mbc, ram, battery, timer, rumble)
+        let (mbc, ram, battery, timer, rumble) = match code {
+            0x00 => (Mbc::None, false, false, false, false),
+            0x01 => (Mbc::Mbc1, false, false, false, false),
+            0x02 => (Mbc::Mbc1, true, false, false, false),
+            0x03 => (Mbc::Mbc1, true, true, false, false),
+            0x05 => (Mbc::Mbc2, false, false, false, false),
+            0x06 => (Mbc::Mbc2, false, true, false, false),
+            0x08 => (Mbc::None, true, false, false, false),
+            0x09 => (Mbc::None, true, true, false, false),
+            0x0B => (Mbc::Mmm01, false, false, false, false),
+            0x0C => (Mbc::Mmm01, true, false, false, false),
+            0x0D => (Mbc::Mmm01, true, true, false, false),
+            0x0F => (Mbc::Mbc3, false, true, true, false),
+            0x10 => (Mbc::Mbc3, true, true, true, false),
+            0x11 => (Mbc::Mbc3, false, false, false, false),
+            0x12 => (Mbc::Mbc3, true, false, false, false),
+            0x13 => (Mbc::Mbc3, true, true, false, false),
+            0x19 => (Mbc::Mbc5, false, false, false, false),
+            0x1A => (Mbc::Mbc5, true, false, false, false),
+            0x1B => (Mbc::Mbc5, true, true, false, false),
+            0x1C => (Mbc::Mbc5, false, false, false, true),
+            0x1D => (Mbc::Mbc5, true, false, false, true),
+            0x1E => (Mbc::Mbc5, true, true, false, true),
+            0x20 => (Mbc::Mbc6, false, false, false, false),
+            0x22 => (Mbc::Mbc7, true, true, false, true),
+            0xFC => (Mbc::PocketCamera, false, false, false, false),
+            0xFD => (Mbc::Tama5, false, false, false, false),
+            0xFE => (Mbc::HuC3, false, false, false, false),
+            0xFF => (Mbc::HuC1, true, true, false, false),
+            _ => return None
+        };
+        Some(CartridgeType { code, mbc, ram, battery, timer, rumble })
+    }
+}
+
+impl CartridgeHeader {
+    //Parses the header from a ROM image at least HEADER_END bytes long
+    pub fn parse(rom: &[u8]) -> Result<CartridgeHeader, CartridgeError> {
+        let cgb = match rom[CGB_FLAG] {
+            0x80 => CgbSupport::Compatible,
+            0xC0 => CgbSupport::Only,
+            _ => CgbSupport::None
+        };
+        //CGB-era headers shrink the title to make room for the manufacturer
+        //code and CGB flag; older ones use all sixteen bytes for the title
+        let (title_end, manufacturer) = if cgb == CgbSupport::None {
+            (CGB_FLAG + 1, None)
+        } else {
+            let code = &rom[MANUFACTURER..CGB_FLAG];
+            if code.iter().all(|b| b.is_ascii_uppercase()) {
+                (MANUFACTURER, Some(ascii(code)))
+            } else {
+                (CGB_FLAG, None)
+            }
+        };
+        let title = ascii(&rom[TITLE..title_end]);
+
+        let old_licensee = rom[OLD_LICENSEE];
+        let new_licensee = if old_licensee == USE_NEW_LICENSEE {
+            Some(ascii(&rom[NEW_LICENSEE..NEW_LICENSEE + 2]))
+        } else {
+            None
+        };
+
+        let code = rom[CARTRIDGE_TYPE];
+        let cartridge_type = CartridgeType::parse(code)
+            .ok_or_else(|| CartridgeError::BadHeader(format!("unknown cartridge type {:#04x}", code)))?;
+
+        let code = rom[ROM_SIZE];
+        if code > 0x08 {
+            return Err(CartridgeError::BadHeader(format!("unknown ROM size code {:#04x}", code)));
+        }
+        let rom_size = 0x8000 << code;
+
+        let ram_size = match rom[RAM_SIZE] {
+            0x00 => 0,
+            //unofficial 2 KiB size used by a few homebrew images
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(CartridgeError::BadHeader(format!("unknown RAM size code {:#04x}", code)))
+        };
+
+        let destination = if rom[DESTINATION] == 0x00 { Destination::Japan } else { Destination::Overseas };
+
+        let computed_header_checksum = rom[TITLE..HEADER_CHECKSUM].iter()
+            .fold(0u8, |sum, b| sum.wrapping_sub(*b).wrapping_sub(1));
+        let computed_global_checksum = rom.iter().enumerate()
+            .filter(|&(i, _)| i != GLOBAL_CHECKSUM && i != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, b)| sum.wrapping_add(*b as u16));
+
+        Ok(CartridgeHeader {
+            title,
+            manufacturer,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            old_licensee,
+            new_licensee,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            destination,
+            version: rom[VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: ((rom[GLOBAL_CHECKSUM] as u16) << 8) | rom[GLOBAL_CHECKSUM + 1] as u16,
+            computed_header_checksum,
+            computed_global_checksum
+        })
+    }
+
+    //The boot ROM refuses to start a cartridge whose header checksum is wrong
+    pub fn header_checksum_valid(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    //Nothing on hardware checks the global checksum, so a mismatch only
+    //suggests a bad dump or a patched image
+    pub fn global_checksum_valid(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom_size / 0x4000
+    }
+}
+
+//Header strings are upper case ASCII padded with zeroes
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&b| b != 0)
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+        .collect()
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let licensee = match self.new_licensee {
+            Some(ref code) => code.clone(),
+            None => format!("{:02X}", self.old_licensee)
+        };
+        let kind = self.cartridge_type;
+        writeln!(f, "Title:       {}", self.title)?;
+        if let Some(ref manufacturer) = self.manufacturer {
+            writeln!(f, "Manufacturer: {}", manufacturer)?;
+        }
+        writeln!(f, "Licensee:    {}", licensee)?;
+        writeln!(f, "Type:        {:#04x} {:?}{}{}{}{}", kind.code, kind.mbc,
+                 if kind.ram { "+RAM" } else { "" },
+                 if kind.battery { "+BATTERY" } else { "" },
+                 if kind.timer { "+TIMER" } else { "" },
+                 if kind.rumble { "+RUMBLE" } else { "" })?;
+        writeln!(f, "ROM:         {} KiB ({} banks)", self.rom_size / 1024, self.rom_banks())?;
+        writeln!(f, "RAM:         {} KiB", self.ram_size / 1024)?;
+        writeln!(f, "CGB:         {:?}  SGB: {}", self.cgb, self.sgb)?;
+        writeln!(f, "Destination: {:?}  Version: {}", self.destination, self.version)?;
+        writeln!(f, "Header checksum: {:#04x} ({})", self.header_checksum,
+                 if self.header_checksum_valid() { "ok" } else { "BAD" })?;
+        write!(f, "Global checksum: {:#06x} ({})", self.global_checksum,
+               if self.global_checksum_valid() { "ok" } else { "BAD" })
+    }
+}
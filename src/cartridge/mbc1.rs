@@ -0,0 +1,106 @@
+use super::{ram_offset, rom_byte, BankController};
+
+//Offset of the second copy of the Nintendo logo in an MBC1M multicart,
+//where each 256 KiB game starts with its own header
+const MULTICART_LOGO: usize = 0x10 * 0x4000 + 0x0104;
+const LOGO: usize = 0x0104;
+const LOGO_LEN: usize = 0x30;
+
+pub struct Mbc1 {
+    ram_enabled: bool,
+    //5-bit register at 0x2000-0x3FFF
+    bank1: u8,
+    //2-bit register at 0x4000-0x5FFF
+    bank2: u8,
+    //0x6000-0x7FFF, when set bank2 also applies to 0x0000-0x3FFF and RAM
+    advanced_banking: bool,
+    //MBC1M wires bank2 to ROM address bits 18-19 instead of 19-20, leaving
+    //the top bit of bank1 unconnected
+    multicart: bool
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Mbc1 {
+        Mbc1 {
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart: is_multicart(rom)
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn low_bank(&self) -> usize {
+        if self.advanced_banking {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn high_bank(&self) -> usize {
+        let bank1 = if self.multicart { self.bank1 & 0x0F } else { self.bank1 };
+        ((self.bank2 << self.bank2_shift()) | bank1) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking { self.bank2 as usize } else { 0 }
+    }
+}
+
+impl BankController for Mbc1 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, self.low_bank(), addr),
+            _ => rom_byte(rom, self.high_bank(), addr)
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            //writing 0 selects bank 1, but only the full 5-bit value is
+            //checked, so banks 0x20, 0x40 and 0x60 can't be reached here
+            0x2000..=0x3FFF => {
+                self.bank1 = data & 0x1F;
+                if self.bank1 == 0 {
+                    self.bank1 = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.bank2 = data & 0x03,
+            _ => self.advanced_banking = data & 0x01 != 0
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match ram_offset(ram, self.ram_bank(), addr) {
+            Some(offset) => ram[offset],
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank(), addr) {
+            Some(offset) => {
+                ram[offset] = data;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+//MBC1M carts are 1 MiB and repeat the Nintendo logo at the start of bank 0x10
+fn is_multicart(rom: &[u8]) -> bool {
+    rom.len() == 0x100000 && rom[LOGO..LOGO + LOGO_LEN] == rom[MULTICART_LOGO..MULTICART_LOGO + LOGO_LEN]
+}
@@ -0,0 +1,58 @@
+use super::{rom_byte, BankController};
+
+//MBC2 has 512 half-bytes of RAM built in, whatever the header says
+pub const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram_enabled: bool,
+    //4-bit ROM bank for 0x4000-0x7FFF, where 0 selects bank 1
+    rom_bank: u8
+}
+
+impl Mbc2 {
+    pub fn new() -> Mbc2 {
+        Mbc2 {
+            ram_enabled: false,
+            rom_bank: 1
+        }
+    }
+}
+
+impl BankController for Mbc2 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr)
+        }
+    }
+
+    //Both registers live in 0x0000-0x3FFF; address bit 8 picks which one
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match (addr, addr & 0x0100 != 0) {
+            (0x0000..=0x3FFF, false) => self.ram_enabled = data & 0x0F == 0x0A,
+            (0x0000..=0x3FFF, true) => {
+                self.rom_bank = data & 0x0F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    //Only the low nibble is stored; the upper one is open bus and reads high.
+    //The 512 cells repeat across all of 0xA000-0xBFFF.
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        ram[addr as usize & (RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if self.ram_enabled {
+            ram[addr as usize & (RAM_SIZE - 1)] = data & 0x0F;
+        }
+        self.ram_enabled
+    }
+}
@@ -0,0 +1,94 @@
+use super::rtc::{self, Rtc};
+use super::{ram_offset, rom_byte, BankController};
+
+pub struct Mbc3 {
+    //enables both external RAM and the RTC registers
+    ram_enabled: bool,
+    //7-bit ROM bank for 0x4000-0x7FFF, where 0 selects bank 1
+    rom_bank: u8,
+    //0x00-0x03 selects a RAM bank, 0x08-0x0C an RTC register
+    ram_select: u8,
+    rtc: Option<Rtc>
+}
+
+impl Mbc3 {
+    pub fn new(timer: bool) -> Mbc3 {
+        Mbc3 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_select: 0,
+            rtc: if timer { Some(Rtc::new()) } else { None }
+        }
+    }
+}
+
+impl BankController for Mbc3 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr)
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = data & 0x7F;
+                if self.rom_bank == 0 {
+                    self.rom_bank = 1;
+                }
+            }
+            0x4000..=0x5FFF => self.ram_select = data,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(data);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.ram_select, &self.rtc) {
+            (0x00..=0x03, _) => match ram_offset(ram, self.ram_select as usize, addr) {
+                Some(offset) => ram[offset],
+                None => 0xFF
+            },
+            (rtc::SECONDS..=rtc::DAY_HIGH, Some(rtc)) => rtc.read(self.ram_select),
+            _ => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match (self.ram_select, &mut self.rtc) {
+            (0x00..=0x03, _) => match ram_offset(ram, self.ram_select as usize, addr) {
+                Some(offset) => {
+                    ram[offset] = data;
+                    true
+                }
+                None => false
+            },
+            (register @ rtc::SECONDS..=rtc::DAY_HIGH, Some(rtc)) => {
+                rtc.write(register, data);
+                true
+            }
+            _ => false
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.rtc.as_mut()
+    }
+}
@@ -0,0 +1,76 @@
+use super::{ram_offset, rom_byte, BankController};
+
+pub struct Mbc5 {
+    ram_enabled: bool,
+    //9-bit ROM bank for 0x4000-0x7FFF; unlike MBC1 and MBC3, bank 0 can be selected
+    rom_bank: u16,
+    ram_bank: u8,
+    //on rumble carts bit 3 of the RAM bank register drives the motor instead
+    has_rumble: bool,
+    rumble: bool
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false
+        }
+    }
+}
+
+impl BankController for Mbc5 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => rom_byte(rom, 0, addr),
+            _ => rom_byte(rom, self.rom_bank as usize, addr)
+        }
+    }
+
+    fn write_rom(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enabled = data & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | data as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((data as u16 & 0x01) << 8),
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = data & 0x08 != 0;
+                    self.ram_bank = data & 0x07;
+                } else {
+                    self.ram_bank = data & 0x0F;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match ram_offset(ram, self.ram_bank as usize, addr) {
+            Some(offset) => ram[offset],
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        if !self.ram_enabled {
+            return false;
+        }
+        match ram_offset(ram, self.ram_bank as usize, addr) {
+            Some(offset) => {
+                ram[offset] = data;
+                true
+            }
+            None => false
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
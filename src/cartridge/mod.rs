@@ -0,0 +1,418 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::cmp;
+use std::io;
+use std::path::Path;
+
+mod header;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use self::header::{CartridgeHeader, Mbc};
+pub use self::rtc::Rtc;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    controller: Box<dyn BankController>,
+    //external RAM or the RTC was written since the last save
+    dirty: bool
+}
+
+//Maps the CPU's view of 0x0000-0x7FFF and 0xA000-0xBFFF onto the ROM and
+//external RAM, interpreting writes to the ROM area as register writes
+pub trait BankController {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8;
+    fn write_rom(&mut self, addr: u16, data: u8);
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8;
+    //Returns whether the write reached RAM or the RTC
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool;
+
+    //Advances anything on the cartridge that keeps time by `cycles` T-cycles
+    fn tick(&mut self, _cycles: u32) {}
+
+    fn rtc(&mut self) -> Option<&mut Rtc> {
+        None
+    }
+
+    //Whether the game has the rumble motor switched on
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+//32 KiB cartridges with no controller, optionally with up to 8 KiB of RAM
+struct RomOnly;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    //the image is shorter than its header or declared ROM size
+    Truncated { expected: usize, actual: usize },
+    BadHeader(String),
+    Unsupported(Mbc)
+}
+
+impl Cartridge {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Cartridge, CartridgeError> {
+        Cartridge::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(rom: &[u8]) -> Result<Cartridge, CartridgeError> {
+        if rom.len() < header::HEADER_END {
+            return Err(CartridgeError::Truncated { expected: header::HEADER_END, actual: rom.len() });
+        }
+        let header = CartridgeHeader::parse(rom)?;
+        if rom.len() < header.rom_size {
+            return Err(CartridgeError::Truncated { expected: header.rom_size, actual: rom.len() });
+        }
+        if !header.header_checksum_valid() {
+            return Err(CartridgeError::BadHeader("header checksum mismatch".to_string()));
+        }
+        //drop any overdump so the image length is the power of two banking masks against
+        let rom = rom[..header.rom_size].to_vec();
+        let kind = header.cartridge_type;
+        let controller: Box<dyn BankController> = match kind.mbc {
+            Mbc::None => Box::new(RomOnly),
+            Mbc::Mbc1 => Box::new(mbc1::Mbc1::new(&rom)),
+            Mbc::Mbc2 => Box::new(mbc2::Mbc2::new()),
+            Mbc::Mbc3 => Box::new(mbc3::Mbc3::new(kind.timer)),
+            Mbc::Mbc5 => Box::new(mbc5::Mbc5::new(kind.rumble)),
+            mbc => return Err(CartridgeError::Unsupported(mbc))
+        };
+        let ram_size = if kind.mbc == Mbc::Mbc2 { mbc2::RAM_SIZE } else { header.ram_size };
+        let ram = vec![0; ram_size];
+        Ok(Cartridge { header, rom, ram, controller, dirty: false })
+    }
+
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        self.controller.read_rom(&self.rom, addr)
+    }
+
+    pub fn write_rom(&mut self, addr: u16, data: u8) {
+        self.controller.write_rom(addr, data);
+    }
+
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        self.controller.read_ram(&self.ram, addr)
+    }
+
+    pub fn write_ram(&mut self, addr: u16, data: u8) {
+        if self.controller.write_ram(&mut self.ram, addr, data) {
+            self.dirty = true;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.controller.tick(cycles);
+    }
+
+    //The MBC3 real-time clock, if the cartridge has one
+    pub fn rtc(&mut self) -> Option<&mut Rtc> {
+        self.controller.rtc()
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.controller.rumble()
+    }
+
+    //Whether there is anything for a .sav to keep: battery-backed external
+    //RAM or an RTC
+    pub fn has_save(&self) -> bool {
+        let kind = self.header.cartridge_type;
+        kind.battery && (!self.ram.is_empty() || kind.timer)
+    }
+
+    pub fn needs_save(&self) -> bool {
+        self.dirty
+    }
+
+    //Loads external RAM, followed by the RTC footer on MBC3 timer carts, from
+    //a raw .sav file. A missing file just means there is no save yet.
+    pub fn load_save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err)
+        };
+        let len = cmp::min(self.ram.len(), data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+        if let Some(rtc) = self.controller.rtc() {
+            if data.len() > len {
+                rtc.load_footer(&data[len..]);
+            }
+        }
+        Ok(())
+    }
+
+    //Writes the save to a temporary file first so a crash mid-write can't
+    //destroy the previous one
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut data = self.ram.clone();
+        if let Some(rtc) = self.controller.rtc() {
+            data.extend_from_slice(&rtc.footer());
+        }
+        let temp = path.with_extension("sav.tmp");
+        fs::write(&temp, &data)?;
+        fs::rename(&temp, path)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl BankController for RomOnly {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        rom[addr as usize]
+    }
+
+    fn write_rom(&mut self, _addr: u16, _data: u8) {}
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> u8 {
+        match ram_offset(ram, 0, addr) {
+            Some(offset) => ram[offset],
+            None => 0xFF
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, data: u8) -> bool {
+        match ram_offset(ram, 0, addr) {
+            Some(offset) => {
+                ram[offset] = data;
+                true
+            }
+            None => false
+        }
+    }
+}
+
+//Byte at `addr` within 16 KiB ROM bank `bank`, wrapping bank numbers past
+//the end of the image the way the unconnected address lines do
+fn rom_byte(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    rom[(bank * ROM_BANK_SIZE + (addr as usize & (ROM_BANK_SIZE - 1))) & (rom.len() - 1)]
+}
+
+//Offset of `addr` within 8 KiB RAM bank `bank`, or None if there is no RAM
+fn ram_offset(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        return None;
+    }
+    Some((bank * RAM_BANK_SIZE + (addr as usize & (RAM_BANK_SIZE - 1))) & (ram.len() - 1))
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CartridgeError::Io(ref err) => write!(f, "could not read cartridge: {}", err),
+            CartridgeError::Truncated { expected, actual } =>
+                write!(f, "cartridge image is truncated: expected {} bytes, found {}", expected, actual),
+            CartridgeError::BadHeader(ref reason) => write!(f, "bad cartridge header: {}", reason),
+            CartridgeError::Unsupported(mbc) => write!(f, "unsupported memory bank controller: {:?}", mbc)
+        }
+    }
+}
+
+impl error::Error for CartridgeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            CartridgeError::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> CartridgeError {
+        CartridgeError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A ROM image with a valid header whose every bank starts with its own number
+    fn image(cartridge_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000 << rom_size];
+        for (bank, chunk) in rom.chunks_mut(ROM_BANK_SIZE).enumerate() {
+            chunk[0] = bank as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = rom_size;
+        rom[0x0149] = ram_size;
+        rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        rom
+    }
+
+    #[test]
+    fn rom_only_maps_both_banks() {
+        let cartridge = Cartridge::from_bytes(&image(0x00, 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.read_rom(0x0000), 0);
+        assert_eq!(cartridge.read_rom(0x4000), 1);
+    }
+
+    #[test]
+    fn overdump_is_trimmed_to_header_size() {
+        let mut rom = image(0x00, 0x00, 0x00);
+        rom.extend_from_slice(&[0xFF; 0x100]);
+        let cartridge = Cartridge::from_bytes(&rom).unwrap();
+        assert_eq!(cartridge.rom.len(), 0x8000);
+    }
+
+    #[test]
+    fn mbc1_switches_rom_bank() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x01, 0x02, 0x00)).unwrap();
+        cartridge.write_rom(0x2000, 3);
+        assert_eq!(cartridge.read_rom(0x4000), 3);
+        //bank 0 can't be selected into the switchable area, and the bits
+        //above the 5-bit register are ignored
+        cartridge.write_rom(0x2000, 0);
+        assert_eq!(cartridge.read_rom(0x4000), 1);
+        cartridge.write_rom(0x2000, 0x25);
+        assert_eq!(cartridge.read_rom(0x4000), 5);
+    }
+
+    #[test]
+    fn mbc1_upper_bits_reach_large_banks() {
+        let mut rom = image(0x01, 0x05, 0x00);
+        //a logo that differs from bank 0x10's, so this isn't taken for a multicart
+        rom[0x0104] = 0xCE;
+        let mut cartridge = Cartridge::from_bytes(&rom).unwrap();
+        cartridge.write_rom(0x4000, 1);
+        cartridge.write_rom(0x2000, 0);
+        assert_eq!(cartridge.read_rom(0x4000), 0x21);
+        assert_eq!(cartridge.read_rom(0x0000), 0x00);
+        //advanced banking applies the upper bits to the fixed area too
+        cartridge.write_rom(0x6000, 1);
+        assert_eq!(cartridge.read_rom(0x0000), 0x20);
+    }
+
+    #[test]
+    fn mbc1_multicart_shifts_upper_bits_by_four() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x01, 0x05, 0x00)).unwrap();
+        cartridge.write_rom(0x4000, 1);
+        cartridge.write_rom(0x2000, 0x12);
+        assert_eq!(cartridge.read_rom(0x4000), 0x12);
+    }
+
+    #[test]
+    fn mbc5_can_select_bank_zero() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x19, 0x02, 0x00)).unwrap();
+        cartridge.write_rom(0x2000, 0);
+        assert_eq!(cartridge.read_rom(0x4000), 0);
+    }
+
+    #[test]
+    fn mbc5_rumble_bit_drives_motor() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x1C, 0x00, 0x00)).unwrap();
+        assert!(!cartridge.rumble());
+        cartridge.write_rom(0x4000, 0x08);
+        assert!(cartridge.rumble());
+        cartridge.write_rom(0x4000, 0x00);
+        assert!(!cartridge.rumble());
+    }
+
+    #[test]
+    fn mbc2_registers_and_nibble_ram() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x06, 0x02, 0x00)).unwrap();
+        //address bit 8 picks the ROM bank register over RAM enable
+        cartridge.write_rom(0x2100, 3);
+        assert_eq!(cartridge.read_rom(0x4000), 3);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0xAB);
+        assert_eq!(cartridge.read_ram(0xA000), 0xFB);
+        assert_eq!(cartridge.read_ram(0xA200), 0xFB);
+    }
+
+    #[test]
+    fn only_stored_ram_writes_mark_cartridge_dirty() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x03, 0x00, 0x02)).unwrap();
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(!cartridge.needs_save());
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert_eq!(cartridge.read_ram(0xA000), 0x42);
+        assert!(cartridge.needs_save());
+
+        let mut cartridge = Cartridge::from_bytes(&image(0x03, 0x00, 0x00)).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xA000, 0x42);
+        assert!(!cartridge.needs_save());
+    }
+
+    #[test]
+    fn only_battery_backed_ram_or_rtc_is_saved() {
+        let has_save = |cartridge_type, ram_size| {
+            Cartridge::from_bytes(&image(cartridge_type, 0x00, ram_size)).unwrap().has_save()
+        };
+        assert!(has_save(0x03, 0x02));
+        assert!(!has_save(0x03, 0x00));
+        assert!(!has_save(0x02, 0x02));
+        assert!(has_save(0x0F, 0x00));
+    }
+
+    #[test]
+    fn save_round_trips() {
+        let path = std::env::temp_dir().join(format!("rustboy-test-{}.sav", std::process::id()));
+        let mut cartridge = Cartridge::from_bytes(&image(0x03, 0x00, 0x02)).unwrap();
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_ram(0xBFFF, 0x42);
+        cartridge.save(&path).unwrap();
+        assert!(!cartridge.needs_save());
+        let mut loaded = Cartridge::from_bytes(&image(0x03, 0x00, 0x02)).unwrap();
+        loaded.load_save(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        loaded.write_rom(0x0000, 0x0A);
+        assert_eq!(loaded.read_ram(0xBFFF), 0x42);
+    }
+
+    #[test]
+    fn unreadable_save_is_an_error() {
+        let mut cartridge = Cartridge::from_bytes(&image(0x03, 0x00, 0x02)).unwrap();
+        assert!(cartridge.load_save("/nonexistent/rom.sav").is_ok());
+        assert!(cartridge.load_save(std::env::temp_dir()).is_err());
+    }
+
+    #[test]
+    fn truncated_image_is_rejected() {
+        let rom = image(0x01, 0x01, 0x00);
+        match Cartridge::from_bytes(&rom[..0x8000]) {
+            Err(CartridgeError::Truncated { expected: 0x10000, actual: 0x8000 }) => {}
+            _ => panic!("expected a truncated image error")
+        }
+    }
+
+    #[test]
+    fn missing_header_is_rejected() {
+        match Cartridge::from_bytes(&[0; 0x100]) {
+            Err(CartridgeError::Truncated { .. }) => {}
+            _ => panic!("expected a truncated image error")
+        }
+    }
+
+    #[test]
+    fn bad_header_checksum_is_rejected() {
+        let mut rom = image(0x00, 0x00, 0x00);
+        rom[0x014D] ^= 0xFF;
+        match Cartridge::from_bytes(&rom) {
+            Err(CartridgeError::BadHeader(_)) => {}
+            _ => panic!("expected a bad header error")
+        }
+    }
+
+    #[test]
+    fn missing_file_is_an_io_error() {
+        match Cartridge::from_path("/nonexistent/rom.gb") {
+            Err(CartridgeError::Io(_)) => {}
+            _ => panic!("expected an I/O error")
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+//The RTC runs off a 32768 Hz crystal; one second is this many T-cycles
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+//Register numbers as selected through 0x4000-0x5FFF
+pub const SECONDS: u8 = 0x08;
+pub const MINUTES: u8 = 0x09;
+pub const HOURS: u8 = 0x0A;
+pub const DAY_LOW: u8 = 0x0B;
+pub const DAY_HIGH: u8 = 0x0C;
+
+//Save files for RTC carts end with the live registers and the latched ones
+//as 32-bit little-endian words, then a unix timestamp that other emulators
+//write as either 32 or 64 bits
+pub const FOOTER_LEN: usize = 48;
+const SHORT_FOOTER_LEN: usize = 44;
+
+//MBC3 real-time clock. It counts either emulated time, so it stays in step
+//with the game when running fast or paused, or the host's wall clock, so it
+//also keeps time while the emulator isn't running.
+pub struct Rtc {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    //9-bit day counter
+    days: u16,
+    halted: bool,
+    //set when the day counter overflows, cleared only by the game
+    day_carry: bool,
+
+    //copy of the registers taken by the latch sequence, which is what reads see
+    latched: [u8; 5],
+    //0x00 has been written to 0x6000-0x7FFF, so a 0x01 will latch
+    latch_armed: bool,
+
+    //T-cycles into the current second
+    cycles: u32,
+    pub host_sync: bool,
+    //unix time the registers were last brought up to date with the host clock
+    last_sync: u64
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            cycles: 0,
+            host_sync: false,
+            last_sync: unix_time()
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.host_sync || self.halted {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.tick_second();
+        }
+    }
+
+    //Writes to 0x6000-0x7FFF; 0x00 followed by 0x01 latches the clock
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_armed && data == 0x01 {
+            self.sync();
+            self.latched = self.registers();
+        }
+        self.latch_armed = data == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            SECONDS..=DAY_HIGH => self.latched[(register - SECONDS) as usize],
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        self.sync();
+        match register {
+            SECONDS => {
+                self.seconds = data & 0x3F;
+                self.cycles = 0;
+            }
+            MINUTES => self.minutes = data & 0x3F,
+            HOURS => self.hours = data & 0x1F,
+            DAY_LOW => self.days = (self.days & 0x100) | data as u16,
+            DAY_HIGH => {
+                self.days = (self.days & 0xFF) | ((data as u16 & 0x01) << 8);
+                self.halted = data & 0x40 != 0;
+                self.day_carry = data & 0x80 != 0;
+            }
+            _ => return
+        }
+        //writes show up in reads without needing another latch
+        self.latched = self.registers();
+    }
+
+    //Current (unlatched) register values in SECONDS..=DAY_HIGH order
+    pub fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            ((self.days >> 8) as u8 & 0x01) | ((self.halted as u8) << 6) | ((self.day_carry as u8) << 7)
+        ]
+    }
+
+    //Encodes the clock as a save file footer, stamped with the current time
+    pub fn footer(&mut self) -> Vec<u8> {
+        self.sync();
+        let mut footer = Vec::with_capacity(FOOTER_LEN);
+        for &register in self.registers().iter().chain(self.latched.iter()) {
+            footer.extend_from_slice(&(register as u32).to_le_bytes());
+        }
+        footer.extend_from_slice(&self.last_sync.to_le_bytes());
+        footer
+    }
+
+    //Restores the clock from a save file footer. In host sync mode the time
+    //that passed since the save was written is added on.
+    pub fn load_footer(&mut self, footer: &[u8]) {
+        if footer.len() < SHORT_FOOTER_LEN {
+            return;
+        }
+        //each register only uses the low byte of its word
+        let word = |i: usize| footer[i * 4];
+        let timestamp = if footer.len() >= FOOTER_LEN {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&footer[40..48]);
+            u64::from_le_bytes(bytes)
+        } else {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&footer[40..44]);
+            u32::from_le_bytes(bytes) as u64
+        };
+        self.seconds = word(0) & 0x3F;
+        self.minutes = word(1) & 0x3F;
+        self.hours = word(2) & 0x1F;
+        self.days = word(3) as u16 | ((word(4) as u16 & 0x01) << 8);
+        self.halted = word(4) & 0x40 != 0;
+        self.day_carry = word(4) & 0x80 != 0;
+        for i in 0..5 {
+            self.latched[i] = word(5 + i);
+        }
+        self.cycles = 0;
+        self.last_sync = timestamp;
+        self.sync();
+    }
+
+    //Adds whole seconds, e.g. time that passed on the host between sessions
+    pub fn advance(&mut self, mut seconds: u64) {
+        if self.halted {
+            return;
+        }
+        //skip ahead a day at a time while the registers hold sane values
+        if self.seconds < 60 && self.minutes < 60 && self.hours < 24 {
+            let days = self.days as u64 + seconds / SECONDS_PER_DAY;
+            if days > 0x1FF {
+                self.day_carry = true;
+            }
+            self.days = (days & 0x1FF) as u16;
+            seconds %= SECONDS_PER_DAY;
+        }
+        for _ in 0..seconds {
+            self.tick_second();
+        }
+    }
+
+    //Catches up with the host clock when running in host sync mode
+    fn sync(&mut self) {
+        let now = unix_time();
+        if self.host_sync && now > self.last_sync {
+            let elapsed = now - self.last_sync;
+            self.advance(elapsed);
+        }
+        self.last_sync = now;
+    }
+
+    //Each counter is only as wide as its register, so one written past its
+    //limit counts up to the wrap instead of carrying
+    fn tick_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        self.days = (self.days + 1) & 0x1FF;
+        if self.days == 0 {
+            self.day_carry = true;
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Halted so nothing moves while a test runs, with the carry and the top day bit set
+    const SET: [u8; 5] = [12, 34, 5, 0x2A, 0xC1];
+
+    fn footer() -> Vec<u8> {
+        let mut rtc = Rtc::new();
+        for (register, &data) in (SECONDS..=DAY_HIGH).zip(SET.iter()) {
+            rtc.write(register, data);
+        }
+        rtc.footer()
+    }
+
+    #[test]
+    fn footer_round_trips() {
+        let footer = footer();
+        assert_eq!(footer.len(), FOOTER_LEN);
+        let mut rtc = Rtc::new();
+        rtc.load_footer(&footer);
+        assert_eq!(rtc.registers(), SET);
+        assert_eq!(rtc.latched, SET);
+    }
+
+    #[test]
+    fn short_footer_with_32_bit_timestamp_loads() {
+        //the low half of a little-endian 64-bit timestamp is its 32-bit form
+        let footer = footer();
+        let mut rtc = Rtc::new();
+        rtc.load_footer(&footer[..SHORT_FOOTER_LEN]);
+        assert_eq!(rtc.registers(), SET);
+        assert_eq!(rtc.last_sync.to_le_bytes()[..4], footer[40..44]);
+    }
+
+    #[test]
+    fn truncated_footer_is_ignored() {
+        let mut rtc = Rtc::new();
+        rtc.load_footer(&footer()[..SHORT_FOOTER_LEN - 1]);
+        assert_eq!(rtc.registers(), [0; 5]);
+    }
+
+    #[test]
+    fn host_sync_adds_time_since_save() {
+        let mut footer = Rtc::new().footer();
+        footer[40..48].copy_from_slice(&(unix_time() - 90).to_le_bytes());
+        let mut rtc = Rtc::new();
+        rtc.host_sync = true;
+        rtc.load_footer(&footer);
+        let registers = rtc.registers();
+        assert_eq!(registers[1], 1);
+        //a second may tick over between building the footer and loading it
+        assert!(registers[0] == 30 || registers[0] == 31);
+    }
+}
@@ -1,16 +1,40 @@
-use memory;
+use instruction;
+use instruction::{Condition, Instruction, Operand};
+use instruction::Instruction::*;
+use interrupt::Interrupt;
+use boot::Model;
+use bus;
+use timer;
 
-use std;
 use std::fmt;
 
 #[derive(Default)]
 pub struct Cpu {
 
-    pub clock: u8,
+    //T-cycles elapsed since power on
+    pub cycles: u64,
+    //T-cycles of the current instruction already clocked into the bus
+    ticked: u32,
 
     pub pc: u16,
     sp: u16,
 
+    //interrupt master enable, and EI's request to set it after the next instruction
+    ime: bool,
+    ime_scheduled: bool,
+
+    //HALT waits for any pending interrupt, STOP for a joypad press. After a
+    //HALT with IME clear and an interrupt already pending the CPU doesn't
+    //halt but fails to increment pc on the next fetch (the HALT bug).
+    halted: bool,
+    stopped: bool,
+    halt_bug: bool,
+    //an invalid opcode hangs the CPU until power off
+    locked: bool,
+
+    //CGB double speed mode, toggled by STOP while KEY1 is armed
+    pub double_speed: bool,
+
     a: u8,
     f: RegF,
 
@@ -24,670 +48,714 @@ pub struct Cpu {
     l: u8
 }
 
-macro_rules! ld_16 {
-    ($self:expr, $hi:ident, $lo:ident, $memory:expr) => {
-        {
-            $self.$hi = $memory.read_address($self.pc + 2);
-            $self.$lo = $memory.read_address($self.pc + 1);
-            $self.pc += 3;
-        }
-    }
-}
-macro_rules! ld_nn_n {
-    ($self:expr, $hi:ident, $lo:ident, $reg:ident, $memory:expr) => {
-        {
-            let addr = $self.read_reg_16($self.$hi, $self.$lo);
-            $self.$reg = $memory.read_address(addr);
-            $self.pc += 3;
-        }
+impl Cpu {
+    //Sets the registers the boot ROM leaves behind and starts at the cartridge entry point
+    pub fn skip_boot(&mut self, model: Model, header_checksum: u8) {
+        let [af, bc, de, hl] = model.post_boot_registers(header_checksum);
+        self.write_af(af);
+        self.write_bc(bc);
+        self.write_de(de);
+        self.write_hl(hl);
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
     }
-}
 
-macro_rules! inc_16 {
-    ($self:expr, $hi:ident, $lo:ident) => {
-        {
-            let result = $self.read_reg_16($self.$hi, $self.$lo) + 1;
-            $self.$hi = ((result & 0xFF00) >> 8) as u8;
-            $self.$lo = result as u8;
-            $self.pc += 1;
+    //Executes one instruction, or dispatches a pending interrupt, and
+    //returns the T-cycles it took. The bus is clocked an M-cycle ahead of
+    //each fetch and memory access so the hardware sees it at the right time,
+    //and for the rest of the instruction once it's done. A halted, stopped
+    //or locked up CPU idles for one M-cycle per call.
+    pub fn process(&mut self, bus: &mut bus::Bus) -> u32 {
+        self.ticked = 0;
+        let mut cycles = 0;
+        if self.locked {
+            return self.idle(bus);
         }
+        if self.stopped {
+            //only a new press wakes the CPU, a request bit left in IF from
+            //an earlier one doesn't
+            if !bus.take_joypad_edge() {
+                return self.idle(bus);
+            }
+            self.stopped = false;
+        }
+        if self.halted {
+            if bus.pending_interrupts() == 0 {
+                return self.idle(bus);
+            }
+            //waking takes an extra M-cycle, and happens even with IME clear
+            self.halted = false;
+            self.tick(4, bus);
+            cycles += 4;
+        }
+        cycles += match self.service_interrupt(bus) {
+            Some(cycles) => cycles,
+            None => {
+                if self.ime_scheduled {
+                    self.ime_scheduled = false;
+                    self.ime = true;
+                }
+                let decoded = instruction::fetch(self.pc, self.halt_bug, |addr| self.read8(addr, bus));
+                let opcode = decoded.opcode;
+                self.pc = self.pc.wrapping_add(opcode.length as u16);
+                if self.halt_bug {
+                    self.halt_bug = false;
+                    self.pc = self.pc.wrapping_sub(1);
+                }
+                let taken = self.execute(opcode.instruction, decoded.immediate, bus);
+                let cycles = if taken { opcode.cycles_taken } else { opcode.cycles };
+                cycles as u32
+            }
+        };
+        debug_assert!(self.ticked <= cycles, "clocked {} of a {} cycle instruction", self.ticked, cycles);
+        bus.tick(cycles - self.ticked);
+        self.cycles += cycles as u64;
+        cycles
     }
-}
 
-macro_rules! inc {
-    ($self:expr, $reg:ident) => {
-        {
-            $self.$reg += 1;
-            $self.f.z = $self.zero($self.$reg + 1);
-            $self.f.n = false;
-            $self.f.h = $self.half_carry_addition($self.$reg, 1);
-            $self.pc += 1;
-        }
+    fn idle(&mut self, bus: &mut bus::Bus) -> u32 {
+        bus.tick(4);
+        self.cycles += 4;
+        4
     }
-}
 
-macro_rules! dec_16 {
-    ($self:expr, $hi:ident, $lo:ident) => {
-        {
-            let result = $self.read_reg_16($self.$hi, $self.$lo) - 1;
-            $self.$hi = ((result & 0xFF00) >> 8) as u8;
-            $self.$lo = result as u8;
-            $self.pc += 1;
-        }
+    fn tick(&mut self, cycles: u32, bus: &mut bus::Bus) {
+        bus.tick(cycles);
+        self.ticked += cycles;
     }
-}
 
-macro_rules! dec {
-    ($self:expr, $var:ident) => {
-        {
-            $self.$var -= 1;
-            $self.f.z = $self.zero($self.$var);
-            $self.f.n = true;
-            $self.f.h = $self.half_carry_subtraction($self.$var + 1, $self.$var);
-            $self.pc += 1;
-        }
+    fn read8(&mut self, addr: u16, bus: &mut bus::Bus) -> u8 {
+        self.tick(4, bus);
+        bus.read8(addr)
     }
-}
 
-macro_rules! ld_n_d8 {
-    ($self:expr, $var:ident, $memory:expr) => {
-        {
-            let data = $memory.read_address($self.pc + 1);
-            $self.$var = data;
-            $self.pc += 2;
-        }
+    fn write8(&mut self, addr: u16, data: u8, bus: &mut bus::Bus) {
+        self.tick(4, bus);
+        bus.write8(addr, data);
     }
-}
 
-macro_rules! rln {
-    ($self:expr, $reg:ident) => {
-        {
-            let mut val = $self.$reg;
-            val = val << 1;
-            $self.$reg = val;
-            $self.f.z = false;
-            $self.f.n = false;
-            $self.f.h = false;
-            if $self.f.c { val += 1 }
-            if ($self.$reg >> 7) == 1 {
-                $self.f.c = true;
-            } else {
-                $self.f.c = false;
-            }
-            $self.pc += 1;
+    //Jumps to the highest priority pending interrupt if IME is set. Dispatch
+    //takes 5 M-cycles: two idle, two to push pc and one to jump.
+    fn service_interrupt(&mut self, bus: &mut bus::Bus) -> Option<u32> {
+        if !self.ime {
+            return None;
         }
+        let interrupt = Interrupt::highest(bus.pending_interrupts())?;
+        self.ime = false;
+        bus.acknowledge_interrupt(interrupt);
+        self.tick(8, bus);
+        let ret = self.pc;
+        self.push(ret, bus);
+        self.pc = interrupt.vector();
+        Some(20)
     }
-}
 
-impl Cpu {
-    pub fn process(&mut self, memory: &mut memory::Memory) {
-        let opcode = memory.read_address(self.pc);
-         //println!("{:?}: {:#x}", self, opcode);
-        match opcode {
-            //LD BC, d16
-            0x1 => { ld_16!(self, b, c, memory) }
-            //ld (bc),a
-            0x2 => { ld_nn_n!(self, b, c, a, memory) }
-            //INC BC
-            0x3 => { inc_16!(self, b, c) }
-            //INC B
-            0x4 => { inc!(self, b) }
-            //DEC B
-            0x5 => { dec!(self, b) }
-            //LD B,d8
-            0x6 => { ld_n_d8!(self, b, memory) }
-            //DEC BC
-            0xb => { dec_16!(self, b, c) }
-            //INC C
-            0xc => { inc!(self, c) }
-            //DEC C
-            0xd => { dec!(self, c) }
-            //LD C,d8
-            0xe => { ld_n_d8!(self, c, memory) }
-            //LD DE, d16
-            0x11 => { ld_16!(self, d, e, memory) }
-            //INC DE
-            0x13 => { inc_16!(self, d, e) }
-            //DEC D
-            0x15 => { dec!(self, d) }
-            //LD D,d8
-            0x16 => { ld_n_d8!(self, d, memory) }
-            //RLA
-            0x17 => { println!("{:?}", self); rln!(self, a); println!("{:?}", self); }
-            //JR r8
-            0x18 => {
-                let offset = memory.read_address(self.pc +1) as i8;
-                let target = ((self.pc as i32) + offset as i32) as u16;
-                self.pc = 2 + target;
-            }
-            //LD A,(HL+)
-            //- - - -
-            0x2a => {
-                let addr = self.read_reg_16(self.h, self.l);
-                self.a = memory.read_address(addr);
-                self.write_hl(addr + 1);
-                self.pc += 1;
-            }
-            //CPL
-            //- 1 1 -
-            0x2f => {
-                let result = !self.a;
-                self.a = result;
-                self.f.n = true;
-                self.f.h = true;
-                self.pc += 1;
+    //Runs an instruction whose bytes have already been fetched, with pc
+    //pointing past them. Returns whether a conditional branch was taken.
+    fn execute(&mut self, instruction: Instruction, imm: u16, bus: &mut bus::Bus) -> bool {
+        match instruction {
+            Nop => {}
+
+            //LD (a16),SP
+            Ld(Operand::IndA16, Operand::SP) => {
+                let sp = self.sp;
+                self.write8(imm, sp as u8, bus);
+                self.write8(imm.wrapping_add(1), (sp >> 8) as u8, bus);
             }
-            //LD SP, d16
-            //- - - -
-            0x31 => { 
-                self.sp = memory.read_16(self.pc + 1);
-                self.pc += 3;
-            }
-            //XOR A
-            //Z - - -
-            0xaf => {
-                self.a = 0x00;
-                self.f.write(0b1000_0000);
-                self.pc += 1;
-            }
-            //LD HL, d16
-            //- - - -
-            0x21 => {
-                let data = memory.read_16(self.pc +1);
-                self.write_hl(data);
-                self.pc += 3;
+            //LD HL,SP+r8
+            //0 0 H C
+            Ld(Operand::HL, Operand::SpR8) => {
+                let result = self.sp_offset(imm as u8);
+                self.write_hl(result);
             }
-            //LD [HL-}, A
-            //- - - -
-            0x32 => {
-                let addr = self.read_reg_16(self.h, self.l);
-                memory.contents[addr as usize] = self.a;
-                self.write_hl(addr - 1);
-                self.pc += 1;
+            Ld(dst, src) if wide(dst) => {
+                let data = self.read_operand_16(src, imm);
+                self.write_operand_16(dst, data);
             }
-            //LD (HL),d8
-            //- - - -
-            0x36 => {
-                let addr = self.read_reg_16(self.h, self.l);
-                let data = memory.read_address(self.pc +1);
-                memory.contents[addr as usize] = data;
-                self.pc += 2;
+            Ld(dst, src) => {
+                let data = self.read_operand(src, imm, bus);
+                self.write_operand(dst, imm, data, bus);
             }
-            //JR NZ r8
-            //- - - -
-            0x20 => {
-                if !self.f.z {
-                    let offset = memory.read_address(self.pc +1) as i8;
-                    let target = ((self.pc as i32) + offset as i32) as u16;
-                    self.pc = 2 + target;
-                } else { 
-                    self.pc += 2;
-                }
+            Push(reg) => {
+                let data = self.read_operand_16(reg, imm);
+                self.push(data, bus);
             }
-            //LD A,d8
-            //- - - -
-            0x3e => {
-                self.a = memory.read_address(self.pc + 1);
-                self.pc += 2;
+            Pop(reg) => {
+                let data = self.pop(bus);
+                self.write_operand_16(reg, data);
             }
 
-
-            //LD L,d8
+            //INC rr
             //- - - -
-            0x2e => {
-                self.l = memory.read_address(self.pc + 1);
-                self.pc += 2;
+            Inc(reg) if wide(reg) => {
+                let result = self.read_operand_16(reg, imm).wrapping_add(1);
+                self.write_operand_16(reg, result);
             }
-            //LD (C), A
-            //- - - -
-            0xe2 => {
-                let addr = 0xFF00 + self.c as u16;
-                memory.contents[addr as usize] = self.a;
-                self.pc += 1;
+            //INC r
+            //Z 0 H -
+            Inc(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.inc(val);
+                self.write_operand(operand, imm, result, bus);
             }
-
-            //LD [HL], A
+            //DEC rr
             //- - - -
-            0x77 => {
-                let addr = self.read_reg_16(self.h, self.l);
-                memory.contents[addr as usize] = self.a;
-                self.pc += 1;
+            Dec(reg) if wide(reg) => {
+                let result = self.read_operand_16(reg, imm).wrapping_sub(1);
+                self.write_operand_16(reg, result);
             }
-            //LDH (n), A
-            //- - - -
-            0xe0 => {
-                let addr = memory.read_address(self.pc + 1);
-                memory.contents[(0xFF00 + addr as u16) as usize] = self.a;
-                self.pc += 2;
+            //DEC r
+            //Z 1 H -
+            Dec(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.dec(val);
+                self.write_operand(operand, imm, result, bus);
             }
-
-            //LD A, (DE)
-            //- - - -
-            0x1a => {
-                let addr = self.read_reg_16(self.d, self.e);
-                let data = memory.read_address(addr);
-                self.a = data;
-                self.pc += 1;
+            //ADD HL,rr
+            //- 0 H C
+            Add(Operand::HL, reg) => {
+                let val = self.read_operand_16(reg, imm);
+                self.add_hl(val);
             }
-            //CALL a16
-            //- - - -
-            0xcd => {
-                let addr = memory.read_16(self.pc +1);
-                self.sp -= 2;
-                memory.write_16(self.sp, self.pc + 3);
-                self.pc = addr;
+            //ADD SP,r8
+            //0 0 H C
+            Add(Operand::SP, _) => { self.sp = self.sp_offset(imm as u8) }
+            //ADD A,r
+            //Z 0 H C
+            Add(_, src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.add(val);
             }
-            //LD C, A
-            //- - - -
-            0x4f => {
-                self.c = self.a;
-                self.pc += 1;
+            Adc(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.adc(val);
             }
-
-            //PUSH BC
-            //- - - -
-            0xc5 => {
-                self.sp -= 2;
-                memory.write_16(self.sp, self.read_reg_16(self.b, self.c));
-                self.pc += 1;
+            Sub(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.sub(val);
             }
-
-            //POP BC
-            //- - - -
-            0xc1 => {
-                let data = memory.read_16(self.sp);
-                self.write_bc(data);
-                self.sp += 2;
-                self.pc += 1;
+            Sbc(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.sbc(val);
             }
-
-
-            //LD (HL+),A
-            //- - - -
-            0x22 => {
-                let addr = self.read_reg_16(self.h, self.l);
-                memory.contents[addr as usize] = self.a;
-                let result = self.read_reg_16(self.h, self.l) + 1;
-                self.write_hl(result);
-                self.pc += 1;
+            And(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.and(val);
             }
-            //INC HL
-            //- - - -
-            0x23 => {
-                let result = self.read_reg_16(self.h, self.l) + 1;
-                self.write_hl(result);
-                self.pc += 1;
+            Xor(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.xor(val);
             }
-            //RET
-            //- - - -
-            0xc9 => {
-                self.pc = memory.read_16(self.sp);
-                self.sp += 2;
+            Or(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.or(val);
+            }
+            Cp(src) => {
+                let val = self.read_operand(src, imm, bus);
+                self.cp(val);
+            }
+            //Z - 0 C
+            Daa => { self.daa() }
+            //- 1 1 -
+            Cpl => {
+                self.a = !self.a;
+                self.f.n = true;
+                self.f.h = true;
+            }
+            //- 0 0 1
+            Scf => {
+                self.f.n = false;
+                self.f.h = false;
+                self.f.c = true;
+            }
+            //- 0 0 C
+            Ccf => {
+                self.f.n = false;
+                self.f.h = false;
+                self.f.c = !self.f.c;
             }
 
-            //LD A, B
-            //- - - -
-            0x78 => {
-                self.a = self.b;
-                self.pc += 1;
+            //The accumulator rotates always clear Z
+            //0 0 0 C
+            Rlca => {
+                let val = self.a;
+                self.a = self.rlc(val);
+                self.f.z = false;
             }
-            //LD A, E
-            //- - - -
-            0x7b => {
-                self.a = self.e;
-                self.pc += 1;
+            Rrca => {
+                let val = self.a;
+                self.a = self.rrc(val);
+                self.f.z = false;
             }
-            //LD A, L
-            //- - - -
-            0x7d => {
-                self.a = self.l;
-                self.pc += 1;
+            Rla => {
+                let val = self.a;
+                self.a = self.rl(val);
+                self.f.z = false;
+            }
+            Rra => {
+                let val = self.a;
+                self.a = self.rr(val);
+                self.f.z = false;
             }
-            //CP (HL)
-            //Z 1 H C
-            0xbe => {
-                let addr = self.read_reg_16(self.h, self.l);
-                let data = memory.read_address(addr);
-                if (self.a - data) == 0 {
-                    self.f.z = true;
-                } else {
-                    self.f.z = false;
-                }
-                self.f.n = true;
-                self.f.h = self.half_carry_subtraction(self.a, data);
-                self.f.c = self.carry_subtraction(self.a, data);
-                self.pc += 1;
 
+            Jr(cond) => {
+                if self.condition(cond) {
+                    self.pc = self.pc.wrapping_add(imm as u8 as i8 as u16);
+                    return true;
+                }
             }
-            //CP d8
-            //Z 1 H C
-            0xfe => {
-                let data = memory.read_address(self.pc + 1);
-                if (self.a - data) == 0 {
-                    self.f.z = true;
-                } else {
-                    self.f.z = false;
+            //JP (HL)
+            Jp(_, Operand::HL) => { self.pc = self.read_reg_16(self.h, self.l) }
+            Jp(cond, _) => {
+                if self.condition(cond) {
+                    self.pc = imm;
+                    return true;
                 }
-                self.f.n = true;
-                self.f.h = self.half_carry_subtraction(self.a, data);
-                self.f.c = self.carry_subtraction(self.a, data);
-                self.pc += 2;
             }
-            //XOR d8
-            //Z 0 0 0
-            0xee => {
-                let data = memory.read_address(self.pc + 1);
-                self.a = data ^ self.a;
-                self.f.write(0);
-                if self.a == 0 {self.f.z = true}
-                self.pc += 1;
-            }
-            //LD B,E
-            //- - - -
-            0x34 => {
-                self.b = self.e;
-                self.pc += 1;
+            Call(cond) => {
+                if self.condition(cond) {
+                    let ret = self.pc;
+                    self.push(ret, bus);
+                    self.pc = imm;
+                    return true;
+                }
             }
-            //LD (a16), A
-            //- - - -
-            0xea => {
-                self.a = memory.read_address(self.pc + 1);
-                self.pc += 3;
+            Ret(cond) => {
+                if self.condition(cond) {
+                    self.pc = self.pop(bus);
+                    return true;
+                }
             }
-            //DEC A
-            //Z 1 H -
-            0x3d => {
-                self.a -= 1;
-                self.f.n = true;
-                self.f.z = self.zero(self.a);
-                self.f.h = self.half_carry_subtraction(self.a + 1, self.a);
-                self.pc += 1
-
+            //unlike EI, RETI enables interrupts immediately
+            Reti => {
+                self.pc = self.pop(bus);
+                self.ime = true;
             }
-            //JR Z r8
-            //- - - -
-            0x28 => {
-                if self.f.z {
-                    let offset = memory.read_address(self.pc +1) as i8;
-                    let target = ((self.pc as i32) + offset as i32) as u16;
-                    self.pc = 2 + target;
-                } else { 
-                    self.pc += 2;
+            Rst(vector) => {
+                let ret = self.pc;
+                self.push(ret, bus);
+                self.pc = vector as u16;
+            }
+            Halt => {
+                if !self.ime && bus.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
                 }
             }
-            
-
-            //DEC E
-            //Z 1 H -
-            0x1d => {
-                self.e -= 1;
-                self.f.n = true;
-                self.f.z = self.zero(self.e);
-                self.f.h = self.half_carry_subtraction(self.e + 1, self.e);
-                self.pc += 1
+            Stop => { self.stop(bus) }
+            Di => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Ei => { self.ime_scheduled = true }
 
+            //Z 0 0 C
+            Rlc(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.rlc(val);
+                self.write_operand(operand, imm, result, bus);
             }
-            //LD H,A
-            //- - - -
-            0x67 => {
-                self.h = self.a;
-                self.pc += 1;
+            Rrc(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.rrc(val);
+                self.write_operand(operand, imm, result, bus);
             }
-            //LD A,H
-            //- - - -
-            0x7c => {
-                self.a - self.h;
-                self.pc += 1;
+            Rl(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.rl(val);
+                self.write_operand(operand, imm, result, bus);
             }
-            //LD D,A
-            //- - - -
-            0x57 => {
-                self.d = self.a;
-                self.pc += 1;
+            Rr(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = self.rr(val);
+                self.write_operand(operand, imm, result, bus);
             }
-            //INC H
-            //Z 0 H -
-            0x24 => {
-                self.f.z = self.zero(self.h + 1);
-                self.f.n = false;
-                self.f.h = self.half_carry_addition(self.h, 1);
-
-                self.h += 1;
-                self.pc += 1;
+            Sla(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = val << 1;
+                self.set_shift_flags(result, val & 0x80 != 0);
+                self.write_operand(operand, imm, result, bus);
             }
-
-            //LD E, d8
-            //- - - -
-            0x1e => {
-                self.e = memory.read_address(self.pc + 1);
-                self.pc += 2;
+            Sra(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = (val >> 1) | (val & 0x80);
+                self.set_shift_flags(result, val & 0x01 != 0);
+                self.write_operand(operand, imm, result, bus);
             }
-            //LDH a, (a8)
-            //- - - -
-            0xf0 => {
-                let addr = 0xFF00 + memory.read_address(self.pc + 1) as u16;
-                self.a = memory.read_address(addr);
-                self.pc += 2;
-            }
-            //SUB B
-            //Z 1 H C
-            0x90 => {
-                if (self.a - self.b) == 0 {
-                    self.f.z = true;
-                } else {
-                    self.f.z = false;
-                }
-                self.f.n = true;
-                self.f.h = self.half_carry_subtraction(self.a, self.b);
-                self.f.c = self.carry_subtraction(self.a, self.b);
-                self.a = self.a - self.b;
-                self.pc += 1
+            //Z 0 0 0
+            Swap(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = val.rotate_left(4);
+                self.set_shift_flags(result, false);
+                self.write_operand(operand, imm, result, bus);
             }
-            //ADD A, (HL)
-            //Z 0 H C
-            0x86 => {
-                let addr = self.read_reg_16(self.h, self.l);
-                let val = memory.read_address(addr);
-                self.f.z = self.zero(self.a + val);
+            Srl(operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                let result = val >> 1;
+                self.set_shift_flags(result, val & 0x01 != 0);
+                self.write_operand(operand, imm, result, bus);
+            }
+            //Z 0 1 -
+            Bit(bit, operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                self.f.z = val & (1 << bit) == 0;
                 self.f.n = false;
-                self.f.h = self.half_carry_addition(self.a, val);
-                self.f.c = self.carry(self.a, val);
-
-                self.a = self.a + val;
-                self.pc += 1;
+                self.f.h = true;
             }
-            //jp a16
             //- - - -
-            0xc3 => {
-                let addr = memory.read_16(self.pc + 1);
-                self.pc = addr;
+            Res(bit, operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                self.write_operand(operand, imm, val & !(1 << bit), bus);
             }
-            //pop (hl)
-            //- - - -
-            0xe1 => {
-                let data = memory.read_16(self.sp);
-                self.write_hl(data);
-                self.sp += 2;
-                self.pc += 1
-            }
-            //pop (af)
-            //
-            0xf1 => {
-                let data = memory.read_16(self.sp);
-                self.write_af(data);
-                self.sp += 2;
-                self.pc += 1 
-            }
-            //inc a
-            //Z 0 H -
-            0x3c => {
-                self.f.z = self.zero(self.a + 1);
-                self.f.n = false;
-                self.f.h = self.half_carry_addition(self.a, 1);
-
-                self.a += 1;
-                self.pc += 1;
+            Set(bit, operand) => {
+                let val = self.read_operand(operand, imm, bus);
+                self.write_operand(operand, imm, val | (1 << bit), bus);
             }
 
-            //cp a
-            0xbf => {
-                self.f.z = true;
-                self.f.n = true;
-                self.f.h = false;
-                self.f.h = false;
+            Invalid => { self.locked = true }
+            Prefix => unreachable!("CB opcodes are decoded through CB_OPCODES")
+        }
+        false
+    }
 
-                self.pc += 1;
-            }
+    //STOP performs the CGB speed switch if KEY1 is armed, otherwise it
+    //enters low-power mode until a button is pressed. Either way it resets DIV.
+    fn stop(&mut self, bus: &mut bus::Bus) {
+        bus.write8(timer::DIV, 0);
+        if bus.speed_switch_armed() {
+            self.double_speed = !self.double_speed;
+            bus.set_double_speed(self.double_speed);
+        } else {
+            bus.take_joypad_edge();
+            self.stopped = true;
+        }
+    }
 
-            //reti
-            //- - - -
-            0xd9 => {
-                self.pc = memory.read_16(self.sp);
-                self.sp += 2;
-                //set intterupts
-                memory.contents[0xFFFF] = 0xFF;
-            }
-            //EI
-            //- - - -
-            0xfb => {
-                memory.contents[0xFFFF] = 0xFF;
-                self.pc += 1;
-            }
-            //DI
-            //- - - -
-            0xf3 => {
-                memory.contents[0xFFFF] = 0x00;
-                self.pc += 1;
+    fn condition(&self, cond: Option<Condition>) -> bool {
+        match cond {
+            None => true,
+            Some(Condition::NotZero) => !self.f.z,
+            Some(Condition::Zero) => self.f.z,
+            Some(Condition::NotCarry) => !self.f.c,
+            Some(Condition::Carry) => self.f.c
+        }
+    }
+
+    fn read_operand(&mut self, operand: Operand, imm: u16, bus: &mut bus::Bus) -> u8 {
+        match operand {
+            Operand::A => self.a,
+            Operand::B => self.b,
+            Operand::C => self.c,
+            Operand::D => self.d,
+            Operand::E => self.e,
+            Operand::H => self.h,
+            Operand::L => self.l,
+            Operand::D8 => imm as u8,
+            _ => {
+                let addr = self.operand_address(operand, imm);
+                self.read8(addr, bus)
             }
-            //add a, b
-            //Z 0 H C
-            0x80 => {
-                self.f.z = self.zero(self.a + self.b);
-                self.f.n = false;
-                self.f.h = self.half_carry_addition(self.a, self.b);
-                self.f.c = self.carry(self.a, self.b);
+        }
+    }
 
-                self.a = self.a + self.b;
-                self.pc += 1;
+    fn write_operand(&mut self, operand: Operand, imm: u16, data: u8, bus: &mut bus::Bus) {
+        match operand {
+            Operand::A => self.a = data,
+            Operand::B => self.b = data,
+            Operand::C => self.c = data,
+            Operand::D => self.d = data,
+            Operand::E => self.e = data,
+            Operand::H => self.h = data,
+            Operand::L => self.l = data,
+            _ => {
+                let addr = self.operand_address(operand, imm);
+                self.write8(addr, data, bus);
             }
-            //OR C
-            //Z 0 0 0
-            0xb1 => {
-                let data = self.a | self.c;
-                self.a = data;
-                self.f.write(0x00);
-                self.f.z = self.zero(self.a);
-                self.pc += 1;
-            }
-            //OR B
-            //Z 0 0 0
-            0xb0 => {
-                let data = self.a | self.b;
-                self.a = data;
-                self.f.write(0x00);
-                self.f.z = self.zero(self.a);
-                self.pc += 1;
-            }
-            //nop
-            0x0 => {
-                self.pc +=1;
-            }
-
-            //PREFIX CB
-            //
-            0xcb => {
-                let inst = memory.read_address(self.pc + 1);
-                self.pc += 2;
-                match inst {
-                    0x7c => {
-                        if 0b1000_0000 & self.h == 0b1000_0000 {
-                            self.f.z = false;
-                        } else {
-                            self.f.z = true;
-                        }
-                    }
-                    //RL C
-                    //Z 0 0 C
-                    0x11 => {
-                        let val = self.c;
-                        self.c = self.rotate_left(val);
-                        self.f.z = self.zero(self.c);
-                        self.f.n = false;
-                        self.f.h = false;
-                        if (val >> 7) == 1 {
-                            self.f.c = true;
-                        } else {
-                            self.f.c = false;
-                        }
-                    }
-                    _ => {panic!("Unknown CB instruction: {:#x}", inst)}
-                }
+        }
+    }
+
+    //Resolves a memory operand to its address, applying the (HL+)/(HL-) side effect
+    fn operand_address(&mut self, operand: Operand, imm: u16) -> u16 {
+        match operand {
+            Operand::IndBC => self.read_reg_16(self.b, self.c),
+            Operand::IndDE => self.read_reg_16(self.d, self.e),
+            Operand::IndHL => self.read_reg_16(self.h, self.l),
+            Operand::IndHLInc => {
+                let addr = self.read_reg_16(self.h, self.l);
+                self.write_hl(addr.wrapping_add(1));
+                addr
             }
-            _ => {
-                println!("{:#?}", self);
-                panic!("unrecognized opcode: {:#x}", opcode);
+            Operand::IndHLDec => {
+                let addr = self.read_reg_16(self.h, self.l);
+                self.write_hl(addr.wrapping_sub(1));
+                addr
             }
+            Operand::IndC => 0xFF00 + self.c as u16,
+            Operand::IndA8 => 0xFF00 + (imm & 0xFF),
+            Operand::IndA16 => imm,
+            _ => panic!("Not a memory operand: {:?}", operand)
+        }
     }
-}
-fn rotate_left(&mut self, value:u8) -> u8 {
-    let mut result = value << 1;
-    if self.f.c {result += 1}
-    result // & 0xFF;
-}
 
-fn read_reg_16(&self, reg_hi:u8, reg_lo:u8) -> u16 {
-    ((reg_hi as u16) << 8) + reg_lo as u16
-}
+    fn read_operand_16(&self, operand: Operand, imm: u16) -> u16 {
+        match operand {
+            Operand::AF => self.read_reg_16(self.a, self.f.read()),
+            Operand::BC => self.read_reg_16(self.b, self.c),
+            Operand::DE => self.read_reg_16(self.d, self.e),
+            Operand::HL => self.read_reg_16(self.h, self.l),
+            Operand::SP => self.sp,
+            Operand::D16 => imm,
+            _ => panic!("Not a 16-bit operand: {:?}", operand)
+        }
+    }
 
-fn write_af(&mut self, data:u16) {
-    self.a = ((data & 0xFF00) >> 8) as u8;
-    self.f.write(data as u8);
-}
+    fn write_operand_16(&mut self, operand: Operand, data: u16) {
+        match operand {
+            Operand::AF => self.write_af(data),
+            Operand::BC => self.write_bc(data),
+            Operand::DE => self.write_de(data),
+            Operand::HL => self.write_hl(data),
+            Operand::SP => self.sp = data,
+            _ => panic!("Not a 16-bit operand: {:?}", operand)
+        }
+    }
 
-fn write_bc(&mut self, data:u16) {
-    self.b = ((data & 0xFF00) >> 8) as u8;
-    self.c = data as u8;
-}
+    //The high byte goes first, as on hardware
+    fn push(&mut self, data:u16, bus: &mut bus::Bus) {
+        self.sp = self.sp.wrapping_sub(2);
+        let sp = self.sp;
+        self.write8(sp.wrapping_add(1), (data >> 8) as u8, bus);
+        self.write8(sp, data as u8, bus);
+    }
 
-fn write_de(&mut self, data:u16) {
-    self.d = ((data & 0xFF00) >> 8) as u8;
-    self.e = data as u8;
-}
+    fn pop(&mut self, bus: &mut bus::Bus) -> u16 {
+        let sp = self.sp;
+        let low = self.read8(sp, bus) as u16;
+        let high = self.read8(sp.wrapping_add(1), bus) as u16;
+        self.sp = self.sp.wrapping_add(2);
+        high << 8 | low
+    }
 
-fn write_hl(&mut self, data:u16) {
-    self.h = ((data & 0xFF00) >> 8) as u8;
-    self.l = data as u8;
-}
+    //Z 0 H -
+    fn inc(&mut self, val:u8) -> u8 {
+        let result = val.wrapping_add(1);
+        self.f.z = self.zero(result);
+        self.f.n = false;
+        self.f.h = self.half_carry_addition(val, 1);
+        result
+    }
 
-fn half_carry_addition(&self, lhs:u8, rhs:u8) -> bool {
-    ((lhs & 0x0F) + (rhs & 0x0F) & 0x10) == 0x10
-}
+    //Z 1 H -
+    fn dec(&mut self, val:u8) -> u8 {
+        let result = val.wrapping_sub(1);
+        self.f.z = self.zero(result);
+        self.f.n = true;
+        self.f.h = self.half_carry_subtraction(val, 1);
+        result
+    }
 
-fn half_carry_subtraction(&self, lhs:u8, rhs:u8) -> bool {
-    ((lhs & 0x0F) - (rhs & 0x0F) & 0b0000_1000) == 0b0000_1000
-}
+    //Z 0 H C
+    fn add(&mut self, val:u8) {
+        let result = self.a.wrapping_add(val);
+        self.f.z = self.zero(result);
+        self.f.n = false;
+        self.f.h = self.half_carry_addition(self.a, val);
+        self.f.c = self.carry(self.a, val);
+        self.a = result;
+    }
 
-fn carry(&self, lhs:u8, rhs:u8) -> bool {
-    lhs as u16 + rhs as u16 > 255
-}
-fn carry_subtraction(&self, lhs:u8, rhs:u8) -> bool {
-    lhs >= 128 && rhs < 128
-}
+    //Z 0 H C
+    fn adc(&mut self, val:u8) {
+        let carry = self.f.c as u8;
+        let result = self.a.wrapping_add(val).wrapping_add(carry);
+        self.f.z = self.zero(result);
+        self.f.n = false;
+        self.f.h = (self.a & 0x0F) + (val & 0x0F) + carry > 0x0F;
+        self.f.c = self.a as u16 + val as u16 + carry as u16 > 0xFF;
+        self.a = result;
+    }
+
+    //Z 1 H C
+    fn sub(&mut self, val:u8) {
+        self.cp(val);
+        self.a = self.a.wrapping_sub(val);
+    }
+
+    //Z 1 H C
+    fn sbc(&mut self, val:u8) {
+        let carry = self.f.c as u8;
+        let result = self.a.wrapping_sub(val).wrapping_sub(carry);
+        self.f.z = self.zero(result);
+        self.f.n = true;
+        self.f.h = (self.a & 0x0F) < (val & 0x0F) + carry;
+        self.f.c = (self.a as u16) < val as u16 + carry as u16;
+        self.a = result;
+    }
+
+    //Z 0 1 0
+    fn and(&mut self, val:u8) {
+        self.a &= val;
+        self.f.write(0b0010_0000);
+        self.f.z = self.zero(self.a);
+    }
 
-fn zero(&self, val:u8) -> bool { 
-    val == 0 
+    //Z 0 0 0
+    fn xor(&mut self, val:u8) {
+        self.a ^= val;
+        self.f.write(0x00);
+        self.f.z = self.zero(self.a);
+    }
+
+    //Z 0 0 0
+    fn or(&mut self, val:u8) {
+        self.a |= val;
+        self.f.write(0x00);
+        self.f.z = self.zero(self.a);
+    }
+
+    //Z 1 H C
+    fn cp(&mut self, val:u8) {
+        self.f.z = self.zero(self.a.wrapping_sub(val));
+        self.f.n = true;
+        self.f.h = self.half_carry_subtraction(self.a, val);
+        self.f.c = self.carry_subtraction(self.a, val);
+    }
+
+    //- 0 H C
+    fn add_hl(&mut self, val:u16) {
+        let hl = self.read_reg_16(self.h, self.l);
+        self.f.n = false;
+        self.f.h = (hl & 0x0FFF) + (val & 0x0FFF) > 0x0FFF;
+        self.f.c = hl as u32 + val as u32 > 0xFFFF;
+        self.write_hl(hl.wrapping_add(val));
+    }
+
+    //SP plus a signed immediate, shared by ADD SP,r8 and LD HL,SP+r8
+    //0 0 H C
+    fn sp_offset(&mut self, val:u8) -> u16 {
+        self.f.z = false;
+        self.f.n = false;
+        self.f.h = self.half_carry_addition(self.sp as u8, val);
+        self.f.c = self.carry(self.sp as u8, val);
+        self.sp.wrapping_add(val as i8 as u16)
+    }
+
+    //Z - 0 C
+    fn daa(&mut self) {
+        let mut adjust = 0;
+        let mut carry = false;
+        if self.f.h || (!self.f.n && (self.a & 0x0F) > 0x09) {
+            adjust |= 0x06;
+        }
+        if self.f.c || (!self.f.n && self.a > 0x99) {
+            adjust |= 0x60;
+            carry = true;
+        }
+        if self.f.n {
+            self.a = self.a.wrapping_sub(adjust);
+        } else {
+            self.a = self.a.wrapping_add(adjust);
+        }
+        self.f.z = self.zero(self.a);
+        self.f.h = false;
+        self.f.c = carry;
+    }
+
+    //Z 0 0 C
+    fn rlc(&mut self, val:u8) -> u8 {
+        let result = val.rotate_left(1);
+        self.set_shift_flags(result, val & 0x80 != 0);
+        result
+    }
+
+    //Z 0 0 C
+    fn rrc(&mut self, val:u8) -> u8 {
+        let result = val.rotate_right(1);
+        self.set_shift_flags(result, val & 0x01 != 0);
+        result
+    }
+
+    //Z 0 0 C
+    fn rl(&mut self, val:u8) -> u8 {
+        let result = (val << 1) | self.f.c as u8;
+        self.set_shift_flags(result, val & 0x80 != 0);
+        result
+    }
+
+    //Z 0 0 C
+    fn rr(&mut self, val:u8) -> u8 {
+        let result = (val >> 1) | ((self.f.c as u8) << 7);
+        self.set_shift_flags(result, val & 0x01 != 0);
+        result
+    }
+
+    fn set_shift_flags(&mut self, result:u8, carry:bool) {
+        self.f.z = self.zero(result);
+        self.f.n = false;
+        self.f.h = false;
+        self.f.c = carry;
+    }
+
+    fn read_reg_16(&self, reg_hi:u8, reg_lo:u8) -> u16 {
+        ((reg_hi as u16) << 8) + reg_lo as u16
+    }
+
+    fn write_af(&mut self, data:u16) {
+        self.a = ((data & 0xFF00) >> 8) as u8;
+        self.f.write(data as u8);
+    }
+
+    fn write_bc(&mut self, data:u16) {
+        self.b = ((data & 0xFF00) >> 8) as u8;
+        self.c = data as u8;
+    }
+
+    fn write_de(&mut self, data:u16) {
+        self.d = ((data & 0xFF00) >> 8) as u8;
+        self.e = data as u8;
+    }
+
+    fn write_hl(&mut self, data:u16) {
+        self.h = ((data & 0xFF00) >> 8) as u8;
+        self.l = data as u8;
+    }
+
+    fn half_carry_addition(&self, lhs:u8, rhs:u8) -> bool {
+        (lhs & 0x0F) + (rhs & 0x0F) > 0x0F
+    }
+
+    fn half_carry_subtraction(&self, lhs:u8, rhs:u8) -> bool {
+        (lhs & 0x0F) < (rhs & 0x0F)
+    }
+
+    fn carry(&self, lhs:u8, rhs:u8) -> bool {
+        lhs as u16 + rhs as u16 > 255
+    }
+
+    fn carry_subtraction(&self, lhs:u8, rhs:u8) -> bool {
+        lhs < rhs
+    }
+
+    fn zero(&self, val:u8) -> bool {
+        val == 0
+    }
 }
+
+//Register pairs, which select the 16-bit form of LD, INC and DEC
+fn wide(operand: Operand) -> bool {
+    matches!(operand, Operand::AF | Operand::BC | Operand::DE | Operand::HL | Operand::SP)
 }
 
 impl fmt::Debug for Cpu {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "
-               PC: {:#x} SP: {:#x}
+               PC: {:#x} SP: {:#x} CYCLES: {} IME: {}
                A: {:#x} F: {:#b}
                B: {:#x} C: {:#x}
                D: {:#x} E: {:#x}
-               H: {:#x} L: {:#x}", 
-               self.pc, self.sp, 
+               H: {:#x} L: {:#x}",
+               self.pc, self.sp, self.cycles, self.ime,
                self.a, self.f.read(),
                self.b, self.c,
                self.d, self.e,
@@ -716,10 +784,263 @@ impl RegF {
     }
     fn read(&self) -> u8 {
         let mut flags = 0x00;
-        if self.z == true {flags = flags + 0b1000_0000};
-        if self.n == true {flags = flags + 0b0100_0000};
-        if self.h == true {flags = flags + 0b0010_0000};
-        if self.c == true {flags = flags + 0b0001_0000};
+        if self.z {flags |= 0b1000_0000};
+        if self.n {flags |= 0b0100_0000};
+        if self.h {flags |= 0b0010_0000};
+        if self.c {flags |= 0b0001_0000};
         flags
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::Bus;
+    use cartridge::Cartridge;
+    use instruction::{CB_OPCODES, OPCODES};
+    use interrupt;
+    use joypad::Button;
+
+    const PROGRAM: u16 = 0xC000;
+
+    //A DMG with the boot ROM skipped, running `program` from work RAM
+    fn machine(program: &[u8]) -> (Cpu, Bus) {
+        let mut rom = vec![0; 0x8000];
+        rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        let mut bus = Bus::new(Model::Dmg, Cartridge::from_bytes(&rom).unwrap(), None);
+        for (offset, &byte) in program.iter().enumerate() {
+            bus.write8(PROGRAM + offset as u16, byte);
+        }
+        let cpu = Cpu { pc: PROGRAM, sp: 0xDFF0, ..Default::default() };
+        (cpu, bus)
+    }
+
+    fn run(program: &[u8], instructions: usize) -> (Cpu, Bus) {
+        let (mut cpu, mut bus) = machine(program);
+        for _ in 0..instructions {
+            cpu.process(&mut bus);
+        }
+        (cpu, bus)
+    }
+
+    #[test]
+    fn daa_adjusts_bcd_addition() {
+        //LD A,$15; ADD A,$27; DAA
+        let (cpu, _) = run(&[0x3E, 0x15, 0xC6, 0x27, 0x27], 3);
+        assert_eq!(cpu.a, 0x42);
+        assert!(!cpu.f.c);
+        //LD A,$99; ADD A,$01; DAA
+        let (cpu, _) = run(&[0x3E, 0x99, 0xC6, 0x01, 0x27], 3);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.f.z && cpu.f.c && !cpu.f.h);
+    }
+
+    #[test]
+    fn daa_adjusts_bcd_subtraction() {
+        //LD A,$42; SUB $15; DAA
+        let (cpu, _) = run(&[0x3E, 0x42, 0xD6, 0x15, 0x27], 3);
+        assert_eq!(cpu.a, 0x27);
+        assert!(cpu.f.n && !cpu.f.c);
+    }
+
+    #[test]
+    fn adc_carries_into_the_half_carry() {
+        //LD A,$0F; SCF; ADC A,$00
+        let (cpu, _) = run(&[0x3E, 0x0F, 0x37, 0xCE, 0x00], 3);
+        assert_eq!(cpu.a, 0x10);
+        assert!(cpu.f.h && !cpu.f.c && !cpu.f.z);
+    }
+
+    #[test]
+    fn sbc_borrows_the_carry() {
+        //LD A,$10; SCF; SBC A,$0F
+        let (cpu, _) = run(&[0x3E, 0x10, 0x37, 0xDE, 0x0F], 3);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.f.z && cpu.f.n && cpu.f.h && !cpu.f.c);
+        //LD A,$00; SCF; SBC A,$FF
+        let (cpu, _) = run(&[0x3E, 0x00, 0x37, 0xDE, 0xFF], 3);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.f.h && cpu.f.c);
+    }
+
+    #[test]
+    fn sp_offset_flags_come_from_the_low_byte() {
+        //LD SP,$00FF; ADD SP,1
+        let (cpu, _) = run(&[0x31, 0xFF, 0x00, 0xE8, 0x01], 2);
+        assert_eq!(cpu.sp, 0x0100);
+        assert!(cpu.f.h && cpu.f.c && !cpu.f.z && !cpu.f.n);
+        //LD SP,$0100; LD HL,SP-1
+        let (cpu, _) = run(&[0x31, 0x00, 0x01, 0xF8, 0xFF], 2);
+        assert_eq!(cpu.read_reg_16(cpu.h, cpu.l), 0x00FF);
+        assert!(!cpu.f.h && !cpu.f.c);
+    }
+
+    #[test]
+    fn swap_exchanges_nibbles() {
+        //LD A,$F1; SWAP A
+        let (cpu, _) = run(&[0x3E, 0xF1, 0xCB, 0x37], 2);
+        assert_eq!(cpu.a, 0x1F);
+        assert_eq!(cpu.f.read(), 0x00);
+    }
+
+    #[test]
+    fn rl_rotates_through_carry() {
+        //SCF; LD B,$80; RL B
+        let (cpu, _) = run(&[0x37, 0x06, 0x80, 0xCB, 0x10], 3);
+        assert_eq!(cpu.b, 0x01);
+        assert!(cpu.f.c && !cpu.f.z);
+    }
+
+    #[test]
+    fn sra_keeps_the_sign_bit() {
+        //LD A,$81; SRA A
+        let (cpu, _) = run(&[0x3E, 0x81, 0xCB, 0x2F], 2);
+        assert_eq!(cpu.a, 0xC0);
+        assert!(cpu.f.c);
+    }
+
+    #[test]
+    fn bit_operations_on_memory() {
+        //LD HL,$C100; LD (HL),$00; SET 3,(HL); BIT 3,(HL); RES 3,(HL)
+        let (mut cpu, mut bus) = machine(&[0x21, 0x00, 0xC1, 0x36, 0x00, 0xCB, 0xDE, 0xCB, 0x5E, 0xCB, 0x9E]);
+        cpu.process(&mut bus);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.process(&mut bus), 16);
+        assert_eq!(bus.read8(0xC100), 0x08);
+        assert_eq!(cpu.process(&mut bus), 12);
+        assert!(!cpu.f.z && cpu.f.h && !cpu.f.n);
+        cpu.process(&mut bus);
+        assert_eq!(bus.read8(0xC100), 0x00);
+    }
+
+    //Runs every opcode once from a fresh machine. Debug builds also check
+    //that no instruction clocks the bus for longer than it takes.
+    #[test]
+    fn every_opcode_takes_its_table_time() {
+        for (code, opcode) in OPCODES.iter().enumerate() {
+            if opcode.instruction == Prefix {
+                continue;
+            }
+            let (mut cpu, mut bus) = machine(&[code as u8, 0x00, 0xC0]);
+            let cycles = cpu.process(&mut bus);
+            assert!(cycles == opcode.cycles as u32 || cycles == opcode.cycles_taken as u32,
+                    "{:#04x} took {} cycles", code, cycles);
+            assert_eq!(cpu.cycles, cycles as u64);
+        }
+        for (code, opcode) in CB_OPCODES.iter().enumerate() {
+            let (mut cpu, mut bus) = machine(&[0xCB, code as u8]);
+            assert_eq!(cpu.process(&mut bus), opcode.cycles as u32, "CB {:#04x}", code);
+        }
+    }
+
+    #[test]
+    fn push_and_pop_round_trip() {
+        //LD BC,$1234; PUSH BC; POP DE
+        let (mut cpu, mut bus) = machine(&[0x01, 0x34, 0x12, 0xC5, 0xD1]);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.process(&mut bus), 16);
+        assert_eq!(bus.read8(0xDFEF), 0x12);
+        assert_eq!(bus.read8(0xDFEE), 0x34);
+        assert_eq!(cpu.process(&mut bus), 12);
+        assert_eq!(cpu.read_reg_16(cpu.d, cpu.e), 0x1234);
+        assert_eq!(cpu.sp, 0xDFF0);
+    }
+
+    #[test]
+    fn conditional_branches_cost_more_when_taken() {
+        //JR Z,+0 with Z clear, then XOR A; JR Z,+0 with Z set
+        let (mut cpu, mut bus) = machine(&[0x28, 0x00, 0xAF, 0x28, 0x00]);
+        assert_eq!(cpu.process(&mut bus), 8);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.process(&mut bus), 12);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        //EI; NOP; NOP
+        let (mut cpu, mut bus) = machine(&[0xFB, 0x00, 0x00]);
+        bus.write8(interrupt::IE, Interrupt::VBlank.bit() | Interrupt::Timer.bit());
+        bus.request_interrupt(Interrupt::Timer);
+        bus.request_interrupt(Interrupt::VBlank);
+        cpu.process(&mut bus);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.pc, PROGRAM + 2);
+        assert_eq!(cpu.process(&mut bus), 20);
+        assert_eq!(cpu.pc, Interrupt::VBlank.vector());
+        assert_eq!(cpu.sp, 0xDFEE);
+        assert_eq!(bus.read8(0xDFEE), 0x02);
+        assert_eq!(bus.pending_interrupts(), Interrupt::Timer.bit());
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn di_cancels_a_pending_ei() {
+        //EI; DI; NOP
+        let (mut cpu, mut bus) = machine(&[0xFB, 0xF3, 0x00]);
+        bus.write8(interrupt::IE, Interrupt::VBlank.bit());
+        bus.request_interrupt(Interrupt::VBlank);
+        for _ in 0..3 {
+            cpu.process(&mut bus);
+        }
+        assert_eq!(cpu.pc, PROGRAM + 3);
+    }
+
+    #[test]
+    fn reti_enables_interrupts_immediately() {
+        //RETI, returning to whatever is on the stack
+        let (mut cpu, mut bus) = machine(&[0xD9]);
+        bus.write8(interrupt::IE, Interrupt::Serial.bit());
+        bus.request_interrupt(Interrupt::Serial);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.process(&mut bus), 20);
+        assert_eq!(cpu.pc, Interrupt::Serial.vector());
+    }
+
+    #[test]
+    fn halt_bug_repeats_the_next_byte() {
+        //HALT; INC A with IME clear and an interrupt already pending
+        let (mut cpu, mut bus) = machine(&[0x76, 0x3C, 0x00]);
+        bus.write8(interrupt::IE, Interrupt::Timer.bit());
+        bus.request_interrupt(Interrupt::Timer);
+        for _ in 0..3 {
+            cpu.process(&mut bus);
+        }
+        assert!(!cpu.halted);
+        assert_eq!(cpu.a, 2);
+        assert_eq!(cpu.pc, PROGRAM + 2);
+    }
+
+    #[test]
+    fn halt_waits_for_an_interrupt() {
+        //EI; HALT; NOP
+        let (mut cpu, mut bus) = machine(&[0xFB, 0x76, 0x00]);
+        bus.write8(interrupt::IE, Interrupt::Timer.bit());
+        cpu.process(&mut bus);
+        cpu.process(&mut bus);
+        assert_eq!(cpu.process(&mut bus), 4);
+        assert!(cpu.halted);
+        bus.request_interrupt(Interrupt::Timer);
+        //the wake-up M-cycle plus dispatch
+        assert_eq!(cpu.process(&mut bus), 24);
+        assert_eq!(cpu.pc, Interrupt::Timer.vector());
+    }
+
+    #[test]
+    fn stop_waits_for_a_new_press() {
+        //LD A,$10; LDH ($00),A selects the action buttons, then STOP
+        let (mut cpu, mut bus) = machine(&[0x3E, 0x10, 0xE0, 0x00, 0x10, 0x00, 0x00]);
+        bus.press(Button::Start);
+        for _ in 0..3 {
+            cpu.process(&mut bus);
+        }
+        assert!(cpu.stopped);
+        //the request bit left by the earlier press doesn't wake it
+        assert_ne!(bus.read8(interrupt::IF) & Interrupt::Joypad.bit(), 0);
+        cpu.process(&mut bus);
+        assert!(cpu.stopped);
+        bus.press(Button::A);
+        cpu.process(&mut bus);
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, PROGRAM + 7);
+    }
+}
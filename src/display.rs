@@ -1,44 +1,122 @@
 extern crate sdl2;
 
-use self::sdl2::pixels;
-use self::sdl2::pixels::Color;
-use sdl2::rect::{Point};
-use self::sdl2::keyboard::Keycode;
+use self::sdl2::pixels::PixelFormatEnum;
+use self::sdl2::render::{Canvas, Texture, TextureCreator};
+use self::sdl2::sys::render::SDL_RENDERER_PRESENTVSYNC;
+use self::sdl2::video::{Window, WindowContext};
 
-use self::sdl2::gfx::primitives::DrawRenderer;
+use ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
 
-const SCREEN_WIDTH: u32 = 160;
-const SCREEN_HEIGHT: u32 = 144;
+//Window pixels per Game Boy pixel
+const SCALE: u32 = 3;
+//Below this refresh rate waiting for vsync would hold emulation under 59.73 Hz
+const MIN_VSYNC_RATE: i32 = 59;
+
+//RGB for shades 0 (lightest) to 3
+const SHADES: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00]
+];
 
 pub struct Display {
-    canvas: sdl2::render::Canvas<sdl2::video::Window>,
+    canvas: Canvas<Window>,
+    //presenting waits for the host display's refresh
+    vsync: bool,
+    //kept for the controller and audio subsystems
+    pub sdl_context: sdl2::Sdl,
     pub event_pump: sdl2::EventPump
 }
 
 impl Display {
-    pub fn new() -> Display {
+    pub fn new(vsync: bool) -> Display {
         let sdl_context = sdl2::init().unwrap();
         let video_subsys = sdl_context.video().unwrap();
-        let window = video_subsys.window("rustboy", SCREEN_WIDTH, SCREEN_HEIGHT)
+        let window = video_subsys.window("rustboy", SCREEN_WIDTH as u32 * SCALE, SCREEN_HEIGHT as u32 * SCALE)
             .position_centered()
             .opengl()
             .build()
             .unwrap();
 
-        let mut canvas = window.into_canvas().build().unwrap();
-        let mut event_pump = sdl_context.event_pump().unwrap();
+        //a rate of 0 means SDL doesn't know it
+        let rate = window.display_index()
+            .and_then(|index| video_subsys.desktop_display_mode(index))
+            .map(|mode| mode.refresh_rate)
+            .unwrap_or(0);
+        let vsync = if vsync && rate != 0 && rate < MIN_VSYNC_RATE {
+            eprintln!("display refreshes at {} Hz, too slow for vsync", rate);
+            false
+        } else {
+            vsync
+        };
+        let mut canvas = if vsync {
+            window.into_canvas().present_vsync().build().unwrap()
+        } else {
+            window.into_canvas().build().unwrap()
+        };
+        //the driver is free to ignore the request
+        let vsync = canvas.info().flags & SDL_RENDERER_PRESENTVSYNC != 0;
+        let event_pump = sdl_context.event_pump().unwrap();
 
         canvas.clear();
         canvas.present();
         Display {
-            canvas: canvas,
-            event_pump: event_pump
+            canvas,
+            vsync,
+            sdl_context,
+            event_pump
         }
     }
-    pub fn update(&mut self) {
-        self.canvas.set_draw_color(Color::RGB(128, 128, 128));
-        self.canvas.draw_point(Point::new(60 as i32, 60 as i32)).unwrap();
-        self.canvas.clear();
+
+    //Whether presenting paces emulation, or something else has to
+    pub fn vsync(&self) -> bool {
+        self.vsync
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).unwrap();
+    }
+
+    //Textures can't outlive their creator, so the caller keeps it for the Screen
+    pub fn texture_creator(&self) -> TextureCreator<WindowContext> {
+        self.canvas.texture_creator()
+    }
+
+    //Shows the screen's last frame, scaled up to fill the window. With vsync
+    //on this blocks until the next refresh.
+    pub fn present(&mut self, screen: &Screen) {
+        self.canvas.copy(&screen.texture, None, None).unwrap();
         self.canvas.present();
     }
 }
+
+//The Game Boy screen as a texture, created once and updated each frame
+pub struct Screen<'a> {
+    texture: Texture<'a>
+}
+
+impl<'a> Screen<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> Screen<'a> {
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+            .unwrap();
+        texture.with_lock(None, |buffer, _| {
+            for byte in buffer.iter_mut() {
+                *byte = 0xFF;
+            }
+        }).unwrap();
+        Screen { texture }
+    }
+
+    //Converts a frame of shades from the PPU for the next present
+    pub fn draw(&mut self, frame: &[u8]) {
+        self.texture.with_lock(None, |buffer, pitch| {
+            for (row, line) in buffer.chunks_mut(pitch).zip(frame.chunks(SCREEN_WIDTH)) {
+                for (pixel, &shade) in row.chunks_mut(3).zip(line.iter()) {
+                    pixel.copy_from_slice(&SHADES[shade as usize]);
+                }
+            }
+        }).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+extern crate sdl2;
+extern crate toml;
+
+use self::sdl2::controller::{Axis, Button as PadButton, GameController};
+use self::sdl2::event::Event;
+use self::sdl2::haptic::Haptic;
+use self::sdl2::keyboard::Keycode;
+use self::sdl2::{GameControllerSubsystem, HapticSubsystem};
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bus;
+use joypad::Button;
+
+//How far a stick has to be pushed to count as a press
+const AXIS_THRESHOLD: i16 = 16384;
+//SDL_HAPTIC_INFINITY, rumble until stopped
+const RUMBLE_FOREVER: u32 = u32::MAX;
+
+//Used for any section the config file leaves out. Keys are SDL key names,
+//controller entries SDL controller button names or an axis with a direction.
+const DEFAULT_BINDINGS: &str = r#"
+[keyboard]
+right = "Right"
+left = "Left"
+up = "Up"
+down = "Down"
+a = "X"
+b = "Z"
+select = "Backspace"
+start = "Return"
+
+[controller]
+right = ["dpright", "+leftx"]
+left = ["dpleft", "-leftx"]
+up = ["dpup", "-lefty"]
+down = ["dpdown", "+lefty"]
+a = "b"
+b = "a"
+select = "back"
+start = "start"
+"#;
+
+pub struct Bindings {
+    keys: HashMap<Keycode, Button>,
+    pad_buttons: HashMap<PadButton, Button>,
+    //an axis and whether it is bound in the positive direction
+    axes: Vec<(Axis, bool, Button)>
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String)
+}
+
+//Something holding a Game Boy button down
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Source {
+    Key(Keycode),
+    PadButton(i32, PadButton),
+    PadAxis(i32, Axis, bool)
+}
+
+//An open controller, with its rumble motor if it has one
+struct Controller {
+    _controller: GameController,
+    haptic: Option<Haptic>
+}
+
+//Turns keyboard and controller events into joypad presses, opening and
+//closing controllers as they are plugged in and out
+pub struct Input {
+    bindings: Bindings,
+    subsystem: Option<GameControllerSubsystem>,
+    haptic_subsystem: Option<HapticSubsystem>,
+    //by instance id
+    controllers: HashMap<i32, Controller>,
+    //a button stays pressed while any source bound to it is held
+    held: HashMap<Source, Button>,
+    //the cartridge's rumble motor is on
+    rumbling: bool
+}
+
+impl Bindings {
+    pub fn defaults() -> Bindings {
+        Bindings::parse(DEFAULT_BINDINGS).unwrap()
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Bindings, ConfigError> {
+        let text = fs::read_to_string(path)?;
+        Bindings::parse(&text)
+    }
+
+    fn parse(text: &str) -> Result<Bindings, ConfigError> {
+        let config: toml::Value = text.parse()?;
+        let defaults: toml::Value = DEFAULT_BINDINGS.parse()?;
+        let section = |name: &str| config.get(name).or_else(|| defaults.get(name));
+        let mut bindings = Bindings { keys: HashMap::new(), pad_buttons: HashMap::new(), axes: Vec::new() };
+        for (button, name) in entries(section("keyboard"))? {
+            let key = Keycode::from_name(&name)
+                .ok_or_else(|| ConfigError::Invalid(format!("unknown key: {}", name)))?;
+            bindings.keys.insert(key, button);
+        }
+        for (button, name) in entries(section("controller"))? {
+            if name.starts_with('+') || name.starts_with('-') {
+                let axis = Axis::from_string(&name[1..])
+                    .ok_or_else(|| ConfigError::Invalid(format!("unknown controller axis: {}", name)))?;
+                bindings.axes.push((axis, name.starts_with('+'), button));
+            } else {
+                let pad_button = PadButton::from_string(&name)
+                    .ok_or_else(|| ConfigError::Invalid(format!("unknown controller button: {}", name)))?;
+                bindings.pad_buttons.insert(pad_button, button);
+            }
+        }
+        Ok(bindings)
+    }
+}
+
+//The (button, input name) pairs of a config section, where each Game Boy
+//button takes one name or a list of them
+fn entries(section: Option<&toml::Value>) -> Result<Vec<(Button, String)>, ConfigError> {
+    let table = match section {
+        Some(toml::Value::Table(table)) => table,
+        Some(_) => return Err(ConfigError::Invalid("bindings must be a table".to_string())),
+        None => return Ok(Vec::new())
+    };
+    let mut entries = Vec::new();
+    for (key, value) in table {
+        let button = button(key)
+            .ok_or_else(|| ConfigError::Invalid(format!("unknown Game Boy button: {}", key)))?;
+        let names = match *value {
+            toml::Value::String(ref name) => vec![name.trim().to_string()],
+            toml::Value::Array(ref names) => names.iter().map(|name| match name.as_str() {
+                Some(name) => Ok(name.trim().to_string()),
+                None => Err(ConfigError::Invalid(format!("bindings for {} must be strings", key)))
+            }).collect::<Result<_, _>>()?,
+            _ => return Err(ConfigError::Invalid(format!("bindings for {} must be strings", key)))
+        };
+        entries.extend(names.into_iter().map(|name| (button, name)));
+    }
+    Ok(entries)
+}
+
+fn button(name: &str) -> Option<Button> {
+    match name.to_ascii_lowercase().as_str() {
+        "right" => Some(Button::Right),
+        "left" => Some(Button::Left),
+        "up" => Some(Button::Up),
+        "down" => Some(Button::Down),
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "select" => Some(Button::Select),
+        "start" => Some(Button::Start),
+        _ => None
+    }
+}
+
+impl Input {
+    //Controllers already connected show up as added events on the first poll
+    pub fn new(bindings: Bindings, sdl_context: &sdl2::Sdl) -> Input {
+        let subsystem = match sdl_context.game_controller() {
+            Ok(subsystem) => Some(subsystem),
+            Err(err) => {
+                eprintln!("controllers unavailable: {}", err);
+                None
+            }
+        };
+        let haptic_subsystem = match sdl_context.haptic() {
+            Ok(subsystem) => Some(subsystem),
+            Err(err) => {
+                eprintln!("rumble unavailable: {}", err);
+                None
+            }
+        };
+        Input {
+            bindings,
+            subsystem,
+            haptic_subsystem,
+            controllers: HashMap::new(),
+            held: HashMap::new(),
+            rumbling: false
+        }
+    }
+
+    //Passes the cartridge's rumble motor on to every controller that has one
+    pub fn set_rumble(&mut self, on: bool) {
+        if on == self.rumbling {
+            return;
+        }
+        self.rumbling = on;
+        for haptic in self.controllers.values_mut().filter_map(|controller| controller.haptic.as_mut()) {
+            if on {
+                haptic.rumble_play(1.0, RUMBLE_FOREVER);
+            } else {
+                haptic.rumble_stop();
+            }
+        }
+    }
+
+    pub fn handle(&mut self, event: &Event, bus: &mut bus::Bus) {
+        match *event {
+            Event::KeyDown {keycode: Some(keycode), repeat: false, ..} => {
+                if let Some(&button) = self.bindings.keys.get(&keycode) {
+                    self.press(Source::Key(keycode), button, bus);
+                }
+            }
+            Event::KeyUp {keycode: Some(keycode), ..} => {
+                self.release(Source::Key(keycode), bus);
+            }
+            Event::ControllerButtonDown {which, button: pad_button, ..} => {
+                if let Some(&button) = self.bindings.pad_buttons.get(&pad_button) {
+                    self.press(Source::PadButton(which, pad_button), button, bus);
+                }
+            }
+            Event::ControllerButtonUp {which, button: pad_button, ..} => {
+                self.release(Source::PadButton(which, pad_button), bus);
+            }
+            Event::ControllerAxisMotion {which, axis, value, ..} => {
+                let bound: Vec<(bool, Button)> = self.bindings.axes.iter()
+                    .filter(|&&(bound, _, _)| bound == axis)
+                    .map(|&(_, positive, button)| (positive, button))
+                    .collect();
+                for (positive, button) in bound {
+                    let source = Source::PadAxis(which, axis, positive);
+                    let pushed = if positive { value > AXIS_THRESHOLD } else { value < -AXIS_THRESHOLD };
+                    if pushed {
+                        self.press(source, button, bus);
+                    } else {
+                        self.release(source, bus);
+                    }
+                }
+            }
+            Event::ControllerDeviceAdded {which, ..} => self.open(which as u32),
+            Event::ControllerDeviceRemoved {which, ..} => {
+                self.controllers.remove(&which);
+                let sources: Vec<Source> = self.held.keys().cloned().filter(|source| match *source {
+                    Source::PadButton(id, _) | Source::PadAxis(id, _, _) => id == which,
+                    Source::Key(_) => false
+                }).collect();
+                for source in sources {
+                    self.release(source, bus);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open(&mut self, index: u32) {
+        let subsystem = match self.subsystem {
+            Some(ref subsystem) => subsystem,
+            None => return
+        };
+        match subsystem.open(index) {
+            Ok(controller) => {
+                println!("controller connected: {}", controller.name());
+                let mut haptic = self.haptic_subsystem.as_ref()
+                    .and_then(|haptic| haptic.open_from_joystick_id(index as i32).ok());
+                if let Some(ref mut haptic) = haptic {
+                    if self.rumbling {
+                        haptic.rumble_play(1.0, RUMBLE_FOREVER);
+                    }
+                }
+                let id = controller.instance_id();
+                self.controllers.insert(id, Controller { _controller: controller, haptic });
+            }
+            Err(err) => eprintln!("could not open controller {}: {}", index, err)
+        }
+    }
+
+    fn press(&mut self, source: Source, button: Button, bus: &mut bus::Bus) {
+        let already = self.held.values().any(|&held| held == button);
+        if self.held.insert(source, button).is_none() && !already {
+            bus.press(button);
+        }
+    }
+
+    fn release(&mut self, source: Source, bus: &mut bus::Bus) {
+        if let Some(button) = self.held.remove(&source) {
+            if !self.held.values().any(|&held| held == button) {
+                bus.release(button);
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "could not read config: {}", err),
+            ConfigError::Parse(ref err) => write!(f, "bad config: {}", err),
+            ConfigError::Invalid(ref reason) => write!(f, "bad config: {}", reason)
+        }
+    }
+}
+
+impl error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            ConfigError::Io(ref err) => Some(err),
+            ConfigError::Parse(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(err)
+    }
+}
@@ -0,0 +1,589 @@
+use bus;
+
+use std::fmt;
+
+//Operand kinds, named after the assembler syntax they stand for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    A, B, C, D, E, H, L,
+    AF, BC, DE, HL, SP,
+    //(BC), (DE), (HL)
+    IndBC, IndDE, IndHL,
+    //(HL+), (HL-)
+    IndHLInc, IndHLDec,
+    //(FF00+C)
+    IndC,
+    //8-bit and 16-bit immediates
+    D8, D16,
+    //(FF00+a8), (a16)
+    IndA8, IndA16,
+    //signed 8-bit immediate
+    R8,
+    //SP plus a signed 8-bit immediate
+    SpR8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    NotZero,
+    Zero,
+    NotCarry,
+    Carry
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    //0xcb, the real instruction lives in CB_OPCODES
+    Prefix,
+    Invalid,
+
+    Ld(Operand, Operand),
+    Push(Operand),
+    Pop(Operand),
+
+    Inc(Operand),
+    Dec(Operand),
+    Add(Operand, Operand),
+    Adc(Operand),
+    Sub(Operand),
+    Sbc(Operand),
+    And(Operand),
+    Xor(Operand),
+    Or(Operand),
+    Cp(Operand),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+
+    Jr(Option<Condition>),
+    Jp(Option<Condition>, Operand),
+    Call(Option<Condition>),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u8),
+
+    Rlc(Operand),
+    Rrc(Operand),
+    Rl(Operand),
+    Rr(Operand),
+    Sla(Operand),
+    Sra(Operand),
+    Swap(Operand),
+    Srl(Operand),
+    Bit(u8, Operand),
+    Res(u8, Operand),
+    Set(u8, Operand)
+}
+
+//Metadata for a single opcode. Cycle counts are in T-cycles; `cycles_taken`
+//only differs from `cycles` for conditional jumps, calls and returns.
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub instruction: Instruction,
+    pub length: u8,
+    pub cycles: u8,
+    pub cycles_taken: u8
+}
+
+//An opcode fetched from memory along with its immediate operand, if any
+pub struct Decoded {
+    pub opcode: &'static Opcode,
+    pub immediate: u16
+}
+
+use self::Condition::*;
+use self::Instruction::*;
+use self::Operand::*;
+
+const fn op(mnemonic: &'static str, instruction: Instruction, length: u8, cycles: u8) -> Opcode {
+    Opcode { mnemonic, instruction, length, cycles, cycles_taken: cycles }
+}
+
+const fn branch(mnemonic: &'static str, instruction: Instruction, length: u8, cycles: u8, cycles_taken: u8) -> Opcode {
+    Opcode { mnemonic, instruction, length, cycles, cycles_taken }
+}
+
+pub static OPCODES: [Opcode; 256] = [
+    /* 0x00 */ op("NOP", Nop, 1, 4),
+    /* 0x01 */ op("LD", Ld(BC, D16), 3, 12),
+    /* 0x02 */ op("LD", Ld(IndBC, A), 1, 8),
+    /* 0x03 */ op("INC", Inc(BC), 1, 8),
+    /* 0x04 */ op("INC", Inc(B), 1, 4),
+    /* 0x05 */ op("DEC", Dec(B), 1, 4),
+    /* 0x06 */ op("LD", Ld(B, D8), 2, 8),
+    /* 0x07 */ op("RLCA", Rlca, 1, 4),
+    /* 0x08 */ op("LD", Ld(IndA16, SP), 3, 20),
+    /* 0x09 */ op("ADD", Add(HL, BC), 1, 8),
+    /* 0x0a */ op("LD", Ld(A, IndBC), 1, 8),
+    /* 0x0b */ op("DEC", Dec(BC), 1, 8),
+    /* 0x0c */ op("INC", Inc(C), 1, 4),
+    /* 0x0d */ op("DEC", Dec(C), 1, 4),
+    /* 0x0e */ op("LD", Ld(C, D8), 2, 8),
+    /* 0x0f */ op("RRCA", Rrca, 1, 4),
+    /* 0x10 */ op("STOP", Stop, 2, 4),
+    /* 0x11 */ op("LD", Ld(DE, D16), 3, 12),
+    /* 0x12 */ op("LD", Ld(IndDE, A), 1, 8),
+    /* 0x13 */ op("INC", Inc(DE), 1, 8),
+    /* 0x14 */ op("INC", Inc(D), 1, 4),
+    /* 0x15 */ op("DEC", Dec(D), 1, 4),
+    /* 0x16 */ op("LD", Ld(D, D8), 2, 8),
+    /* 0x17 */ op("RLA", Rla, 1, 4),
+    /* 0x18 */ op("JR", Jr(None), 2, 12),
+    /* 0x19 */ op("ADD", Add(HL, DE), 1, 8),
+    /* 0x1a */ op("LD", Ld(A, IndDE), 1, 8),
+    /* 0x1b */ op("DEC", Dec(DE), 1, 8),
+    /* 0x1c */ op("INC", Inc(E), 1, 4),
+    /* 0x1d */ op("DEC", Dec(E), 1, 4),
+    /* 0x1e */ op("LD", Ld(E, D8), 2, 8),
+    /* 0x1f */ op("RRA", Rra, 1, 4),
+    /* 0x20 */ branch("JR", Jr(Some(NotZero)), 2, 8, 12),
+    /* 0x21 */ op("LD", Ld(HL, D16), 3, 12),
+    /* 0x22 */ op("LD", Ld(IndHLInc, A), 1, 8),
+    /* 0x23 */ op("INC", Inc(HL), 1, 8),
+    /* 0x24 */ op("INC", Inc(H), 1, 4),
+    /* 0x25 */ op("DEC", Dec(H), 1, 4),
+    /* 0x26 */ op("LD", Ld(H, D8), 2, 8),
+    /* 0x27 */ op("DAA", Daa, 1, 4),
+    /* 0x28 */ branch("JR", Jr(Some(Zero)), 2, 8, 12),
+    /* 0x29 */ op("ADD", Add(HL, HL), 1, 8),
+    /* 0x2a */ op("LD", Ld(A, IndHLInc), 1, 8),
+    /* 0x2b */ op("DEC", Dec(HL), 1, 8),
+    /* 0x2c */ op("INC", Inc(L), 1, 4),
+    /* 0x2d */ op("DEC", Dec(L), 1, 4),
+    /* 0x2e */ op("LD", Ld(L, D8), 2, 8),
+    /* 0x2f */ op("CPL", Cpl, 1, 4),
+    /* 0x30 */ branch("JR", Jr(Some(NotCarry)), 2, 8, 12),
+    /* 0x31 */ op("LD", Ld(SP, D16), 3, 12),
+    /* 0x32 */ op("LD", Ld(IndHLDec, A), 1, 8),
+    /* 0x33 */ op("INC", Inc(SP), 1, 8),
+    /* 0x34 */ op("INC", Inc(IndHL), 1, 12),
+    /* 0x35 */ op("DEC", Dec(IndHL), 1, 12),
+    /* 0x36 */ op("LD", Ld(IndHL, D8), 2, 12),
+    /* 0x37 */ op("SCF", Scf, 1, 4),
+    /* 0x38 */ branch("JR", Jr(Some(Carry)), 2, 8, 12),
+    /* 0x39 */ op("ADD", Add(HL, SP), 1, 8),
+    /* 0x3a */ op("LD", Ld(A, IndHLDec), 1, 8),
+    /* 0x3b */ op("DEC", Dec(SP), 1, 8),
+    /* 0x3c */ op("INC", Inc(A), 1, 4),
+    /* 0x3d */ op("DEC", Dec(A), 1, 4),
+    /* 0x3e */ op("LD", Ld(A, D8), 2, 8),
+    /* 0x3f */ op("CCF", Ccf, 1, 4),
+    /* 0x40 */ op("LD", Ld(B, B), 1, 4),
+    /* 0x41 */ op("LD", Ld(B, C), 1, 4),
+    /* 0x42 */ op("LD", Ld(B, D), 1, 4),
+    /* 0x43 */ op("LD", Ld(B, E), 1, 4),
+    /* 0x44 */ op("LD", Ld(B, H), 1, 4),
+    /* 0x45 */ op("LD", Ld(B, L), 1, 4),
+    /* 0x46 */ op("LD", Ld(B, IndHL), 1, 8),
+    /* 0x47 */ op("LD", Ld(B, A), 1, 4),
+    /* 0x48 */ op("LD", Ld(C, B), 1, 4),
+    /* 0x49 */ op("LD", Ld(C, C), 1, 4),
+    /* 0x4a */ op("LD", Ld(C, D), 1, 4),
+    /* 0x4b */ op("LD", Ld(C, E), 1, 4),
+    /* 0x4c */ op("LD", Ld(C, H), 1, 4),
+    /* 0x4d */ op("LD", Ld(C, L), 1, 4),
+    /* 0x4e */ op("LD", Ld(C, IndHL), 1, 8),
+    /* 0x4f */ op("LD", Ld(C, A), 1, 4),
+    /* 0x50 */ op("LD", Ld(D, B), 1, 4),
+    /* 0x51 */ op("LD", Ld(D, C), 1, 4),
+    /* 0x52 */ op("LD", Ld(D, D), 1, 4),
+    /* 0x53 */ op("LD", Ld(D, E), 1, 4),
+    /* 0x54 */ op("LD", Ld(D, H), 1, 4),
+    /* 0x55 */ op("LD", Ld(D, L), 1, 4),
+    /* 0x56 */ op("LD", Ld(D, IndHL), 1, 8),
+    /* 0x57 */ op("LD", Ld(D, A), 1, 4),
+    /* 0x58 */ op("LD", Ld(E, B), 1, 4),
+    /* 0x59 */ op("LD", Ld(E, C), 1, 4),
+    /* 0x5a */ op("LD", Ld(E, D), 1, 4),
+    /* 0x5b */ op("LD", Ld(E, E), 1, 4),
+    /* 0x5c */ op("LD", Ld(E, H), 1, 4),
+    /* 0x5d */ op("LD", Ld(E, L), 1, 4),
+    /* 0x5e */ op("LD", Ld(E, IndHL), 1, 8),
+    /* 0x5f */ op("LD", Ld(E, A), 1, 4),
+    /* 0x60 */ op("LD", Ld(H, B), 1, 4),
+    /* 0x61 */ op("LD", Ld(H, C), 1, 4),
+    /* 0x62 */ op("LD", Ld(H, D), 1, 4),
+    /* 0x63 */ op("LD", Ld(H, E), 1, 4),
+    /* 0x64 */ op("LD", Ld(H, H), 1, 4),
+    /* 0x65 */ op("LD", Ld(H, L), 1, 4),
+    /* 0x66 */ op("LD", Ld(H, IndHL), 1, 8),
+    /* 0x67 */ op("LD", Ld(H, A), 1, 4),
+    /* 0x68 */ op("LD", Ld(L, B), 1, 4),
+    /* 0x69 */ op("LD", Ld(L, C), 1, 4),
+    /* 0x6a */ op("LD", Ld(L, D), 1, 4),
+    /* 0x6b */ op("LD", Ld(L, E), 1, 4),
+    /* 0x6c */ op("LD", Ld(L, H), 1, 4),
+    /* 0x6d */ op("LD", Ld(L, L), 1, 4),
+    /* 0x6e */ op("LD", Ld(L, IndHL), 1, 8),
+    /* 0x6f */ op("LD", Ld(L, A), 1, 4),
+    /* 0x70 */ op("LD", Ld(IndHL, B), 1, 8),
+    /* 0x71 */ op("LD", Ld(IndHL, C), 1, 8),
+    /* 0x72 */ op("LD", Ld(IndHL, D), 1, 8),
+    /* 0x73 */ op("LD", Ld(IndHL, E), 1, 8),
+    /* 0x74 */ op("LD", Ld(IndHL, H), 1, 8),
+    /* 0x75 */ op("LD", Ld(IndHL, L), 1, 8),
+    /* 0x76 */ op("HALT", Halt, 1, 4),
+    /* 0x77 */ op("LD", Ld(IndHL, A), 1, 8),
+    /* 0x78 */ op("LD", Ld(A, B), 1, 4),
+    /* 0x79 */ op("LD", Ld(A, C), 1, 4),
+    /* 0x7a */ op("LD", Ld(A, D), 1, 4),
+    /* 0x7b */ op("LD", Ld(A, E), 1, 4),
+    /* 0x7c */ op("LD", Ld(A, H), 1, 4),
+    /* 0x7d */ op("LD", Ld(A, L), 1, 4),
+    /* 0x7e */ op("LD", Ld(A, IndHL), 1, 8),
+    /* 0x7f */ op("LD", Ld(A, A), 1, 4),
+    /* 0x80 */ op("ADD", Add(A, B), 1, 4),
+    /* 0x81 */ op("ADD", Add(A, C), 1, 4),
+    /* 0x82 */ op("ADD", Add(A, D), 1, 4),
+    /* 0x83 */ op("ADD", Add(A, E), 1, 4),
+    /* 0x84 */ op("ADD", Add(A, H), 1, 4),
+    /* 0x85 */ op("ADD", Add(A, L), 1, 4),
+    /* 0x86 */ op("ADD", Add(A, IndHL), 1, 8),
+    /* 0x87 */ op("ADD", Add(A, A), 1, 4),
+    /* 0x88 */ op("ADC", Adc(B), 1, 4),
+    /* 0x89 */ op("ADC", Adc(C), 1, 4),
+    /* 0x8a */ op("ADC", Adc(D), 1, 4),
+    /* 0x8b */ op("ADC", Adc(E), 1, 4),
+    /* 0x8c */ op("ADC", Adc(H), 1, 4),
+    /* 0x8d */ op("ADC", Adc(L), 1, 4),
+    /* 0x8e */ op("ADC", Adc(IndHL), 1, 8),
+    /* 0x8f */ op("ADC", Adc(A), 1, 4),
+    /* 0x90 */ op("SUB", Sub(B), 1, 4),
+    /* 0x91 */ op("SUB", Sub(C), 1, 4),
+    /* 0x92 */ op("SUB", Sub(D), 1, 4),
+    /* 0x93 */ op("SUB", Sub(E), 1, 4),
+    /* 0x94 */ op("SUB", Sub(H), 1, 4),
+    /* 0x95 */ op("SUB", Sub(L), 1, 4),
+    /* 0x96 */ op("SUB", Sub(IndHL), 1, 8),
+    /* 0x97 */ op("SUB", Sub(A), 1, 4),
+    /* 0x98 */ op("SBC", Sbc(B), 1, 4),
+    /* 0x99 */ op("SBC", Sbc(C), 1, 4),
+    /* 0x9a */ op("SBC", Sbc(D), 1, 4),
+    /* 0x9b */ op("SBC", Sbc(E), 1, 4),
+    /* 0x9c */ op("SBC", Sbc(H), 1, 4),
+    /* 0x9d */ op("SBC", Sbc(L), 1, 4),
+    /* 0x9e */ op("SBC", Sbc(IndHL), 1, 8),
+    /* 0x9f */ op("SBC", Sbc(A), 1, 4),
+    /* 0xa0 */ op("AND", And(B), 1, 4),
+    /* 0xa1 */ op("AND", And(C), 1, 4),
+    /* 0xa2 */ op("AND", And(D), 1, 4),
+    /* 0xa3 */ op("AND", And(E), 1, 4),
+    /* 0xa4 */ op("AND", And(H), 1, 4),
+    /* 0xa5 */ op("AND", And(L), 1, 4),
+    /* 0xa6 */ op("AND", And(IndHL), 1, 8),
+    /* 0xa7 */ op("AND", And(A), 1, 4),
+    /* 0xa8 */ op("XOR", Xor(B), 1, 4),
+    /* 0xa9 */ op("XOR", Xor(C), 1, 4),
+    /* 0xaa */ op("XOR", Xor(D), 1, 4),
+    /* 0xab */ op("XOR", Xor(E), 1, 4),
+    /* 0xac */ op("XOR", Xor(H), 1, 4),
+    /* 0xad */ op("XOR", Xor(L), 1, 4),
+    /* 0xae */ op("XOR", Xor(IndHL), 1, 8),
+    /* 0xaf */ op("XOR", Xor(A), 1, 4),
+    /* 0xb0 */ op("OR", Or(B), 1, 4),
+    /* 0xb1 */ op("OR", Or(C), 1, 4),
+    /* 0xb2 */ op("OR", Or(D), 1, 4),
+    /* 0xb3 */ op("OR", Or(E), 1, 4),
+    /* 0xb4 */ op("OR", Or(H), 1, 4),
+    /* 0xb5 */ op("OR", Or(L), 1, 4),
+    /* 0xb6 */ op("OR", Or(IndHL), 1, 8),
+    /* 0xb7 */ op("OR", Or(A), 1, 4),
+    /* 0xb8 */ op("CP", Cp(B), 1, 4),
+    /* 0xb9 */ op("CP", Cp(C), 1, 4),
+    /* 0xba */ op("CP", Cp(D), 1, 4),
+    /* 0xbb */ op("CP", Cp(E), 1, 4),
+    /* 0xbc */ op("CP", Cp(H), 1, 4),
+    /* 0xbd */ op("CP", Cp(L), 1, 4),
+    /* 0xbe */ op("CP", Cp(IndHL), 1, 8),
+    /* 0xbf */ op("CP", Cp(A), 1, 4),
+    /* 0xc0 */ branch("RET", Ret(Some(NotZero)), 1, 8, 20),
+    /* 0xc1 */ op("POP", Pop(BC), 1, 12),
+    /* 0xc2 */ branch("JP", Jp(Some(NotZero), D16), 3, 12, 16),
+    /* 0xc3 */ op("JP", Jp(None, D16), 3, 16),
+    /* 0xc4 */ branch("CALL", Call(Some(NotZero)), 3, 12, 24),
+    /* 0xc5 */ op("PUSH", Push(BC), 1, 16),
+    /* 0xc6 */ op("ADD", Add(A, D8), 2, 8),
+    /* 0xc7 */ op("RST", Rst(0x00), 1, 16),
+    /* 0xc8 */ branch("RET", Ret(Some(Zero)), 1, 8, 20),
+    /* 0xc9 */ op("RET", Ret(None), 1, 16),
+    /* 0xca */ branch("JP", Jp(Some(Zero), D16), 3, 12, 16),
+    /* 0xcb */ op("PREFIX", Prefix, 1, 4),
+    /* 0xcc */ branch("CALL", Call(Some(Zero)), 3, 12, 24),
+    /* 0xcd */ op("CALL", Call(None), 3, 24),
+    /* 0xce */ op("ADC", Adc(D8), 2, 8),
+    /* 0xcf */ op("RST", Rst(0x08), 1, 16),
+    /* 0xd0 */ branch("RET", Ret(Some(NotCarry)), 1, 8, 20),
+    /* 0xd1 */ op("POP", Pop(DE), 1, 12),
+    /* 0xd2 */ branch("JP", Jp(Some(NotCarry), D16), 3, 12, 16),
+    /* 0xd3 */ op("-", Invalid, 1, 4),
+    /* 0xd4 */ branch("CALL", Call(Some(NotCarry)), 3, 12, 24),
+    /* 0xd5 */ op("PUSH", Push(DE), 1, 16),
+    /* 0xd6 */ op("SUB", Sub(D8), 2, 8),
+    /* 0xd7 */ op("RST", Rst(0x10), 1, 16),
+    /* 0xd8 */ branch("RET", Ret(Some(Carry)), 1, 8, 20),
+    /* 0xd9 */ op("RETI", Reti, 1, 16),
+    /* 0xda */ branch("JP", Jp(Some(Carry), D16), 3, 12, 16),
+    /* 0xdb */ op("-", Invalid, 1, 4),
+    /* 0xdc */ branch("CALL", Call(Some(Carry)), 3, 12, 24),
+    /* 0xdd */ op("-", Invalid, 1, 4),
+    /* 0xde */ op("SBC", Sbc(D8), 2, 8),
+    /* 0xdf */ op("RST", Rst(0x18), 1, 16),
+    /* 0xe0 */ op("LDH", Ld(IndA8, A), 2, 12),
+    /* 0xe1 */ op("POP", Pop(HL), 1, 12),
+    /* 0xe2 */ op("LD", Ld(IndC, A), 1, 8),
+    /* 0xe3 */ op("-", Invalid, 1, 4),
+    /* 0xe4 */ op("-", Invalid, 1, 4),
+    /* 0xe5 */ op("PUSH", Push(HL), 1, 16),
+    /* 0xe6 */ op("AND", And(D8), 2, 8),
+    /* 0xe7 */ op("RST", Rst(0x20), 1, 16),
+    /* 0xe8 */ op("ADD", Add(SP, R8), 2, 16),
+    /* 0xe9 */ op("JP", Jp(None, HL), 1, 4),
+    /* 0xea */ op("LD", Ld(IndA16, A), 3, 16),
+    /* 0xeb */ op("-", Invalid, 1, 4),
+    /* 0xec */ op("-", Invalid, 1, 4),
+    /* 0xed */ op("-", Invalid, 1, 4),
+    /* 0xee */ op("XOR", Xor(D8), 2, 8),
+    /* 0xef */ op("RST", Rst(0x28), 1, 16),
+    /* 0xf0 */ op("LDH", Ld(A, IndA8), 2, 12),
+    /* 0xf1 */ op("POP", Pop(AF), 1, 12),
+    /* 0xf2 */ op("LD", Ld(A, IndC), 1, 8),
+    /* 0xf3 */ op("DI", Di, 1, 4),
+    /* 0xf4 */ op("-", Invalid, 1, 4),
+    /* 0xf5 */ op("PUSH", Push(AF), 1, 16),
+    /* 0xf6 */ op("OR", Or(D8), 2, 8),
+    /* 0xf7 */ op("RST", Rst(0x30), 1, 16),
+    /* 0xf8 */ op("LD", Ld(HL, SpR8), 2, 12),
+    /* 0xf9 */ op("LD", Ld(SP, HL), 1, 8),
+    /* 0xfa */ op("LD", Ld(A, IndA16), 3, 16),
+    /* 0xfb */ op("EI", Ei, 1, 4),
+    /* 0xfc */ op("-", Invalid, 1, 4),
+    /* 0xfd */ op("-", Invalid, 1, 4),
+    /* 0xfe */ op("CP", Cp(D8), 2, 8),
+    /* 0xff */ op("RST", Rst(0x38), 1, 16),
+];
+
+pub static CB_OPCODES: [Opcode; 256] = cb_table();
+
+const fn cb_table() -> [Opcode; 256] {
+    let mut table = [op("-", Invalid, 2, 8); 256];
+    let mut code = 0;
+    while code < 256 {
+        table[code] = cb_opcode(code as u8);
+        code += 1;
+    }
+    table
+}
+
+const fn cb_opcode(code: u8) -> Opcode {
+    let operand = match code & 0x07 {
+        0 => B,
+        1 => C,
+        2 => D,
+        3 => E,
+        4 => H,
+        5 => L,
+        6 => IndHL,
+        _ => A
+    };
+    let bit = (code >> 3) & 0x07;
+    let (mnemonic, instruction) = match code >> 3 {
+        0 => ("RLC", Rlc(operand)),
+        1 => ("RRC", Rrc(operand)),
+        2 => ("RL", Rl(operand)),
+        3 => ("RR", Rr(operand)),
+        4 => ("SLA", Sla(operand)),
+        5 => ("SRA", Sra(operand)),
+        6 => ("SWAP", Swap(operand)),
+        7 => ("SRL", Srl(operand)),
+        8..=15 => ("BIT", Bit(bit, operand)),
+        16..=23 => ("RES", Res(bit, operand)),
+        _ => ("SET", Set(bit, operand))
+    };
+    //(HL) costs a read and a write, except BIT which only reads
+    let cycles = match (code & 0x07, code >> 6) {
+        (6, 1) => 12,
+        (6, _) => 16,
+        _ => 8
+    };
+    op(mnemonic, instruction, 2, cycles)
+}
+
+pub fn decode(bus: &bus::Bus, addr: u16) -> Decoded {
+    fetch(addr, false, |addr| bus.read8(addr))
+}
+
+//Reads the instruction at `addr` one byte at a time through `read`, so the
+//CPU can clock the bus ahead of each fetch. With `repeated` the byte at
+//`addr` is read twice, which is what the CPU does for the instruction after
+//a HALT that hit the HALT bug.
+pub fn fetch<F: FnMut(u16) -> u8>(addr: u16, repeated: bool, mut read: F) -> Decoded {
+    let operand_addr = if repeated { addr } else { addr.wrapping_add(1) };
+    let code = read(addr);
+    let opcode = &OPCODES[code as usize];
+    if opcode.instruction == Prefix {
+        let code = read(operand_addr);
+        return Decoded { opcode: &CB_OPCODES[code as usize], immediate: 0 };
+    }
+    let immediate = match (opcode.instruction, opcode.length) {
+        //STOP skips the byte after it without fetching it
+        (Stop, _) => 0,
+        (_, 2) => read(operand_addr) as u16,
+        (_, 3) => {
+            let low = read(operand_addr) as u16;
+            let high = read(operand_addr.wrapping_add(1)) as u16;
+            high << 8 | low
+        }
+        _ => 0
+    };
+    Decoded { opcode, immediate }
+}
+
+//Renders the instruction at `addr` in assembler syntax, e.g. "LD A,($FF44)"
+pub fn disassemble(bus: &bus::Bus, addr: u16) -> String {
+    let decoded = decode(bus, addr);
+    let next = addr.wrapping_add(decoded.opcode.length as u16);
+    format!("{}", Disassembly { decoded: &decoded, next })
+}
+
+struct Disassembly<'a> {
+    decoded: &'a Decoded,
+    next: u16
+}
+
+impl<'a> Disassembly<'a> {
+    fn operand(&self, operand: Operand) -> String {
+        let imm = self.decoded.immediate;
+        match operand {
+            A => "A".to_string(),
+            B => "B".to_string(),
+            C => "C".to_string(),
+            D => "D".to_string(),
+            E => "E".to_string(),
+            H => "H".to_string(),
+            L => "L".to_string(),
+            AF => "AF".to_string(),
+            BC => "BC".to_string(),
+            DE => "DE".to_string(),
+            HL => "HL".to_string(),
+            SP => "SP".to_string(),
+            IndBC => "(BC)".to_string(),
+            IndDE => "(DE)".to_string(),
+            IndHL => "(HL)".to_string(),
+            IndHLInc => "(HL+)".to_string(),
+            IndHLDec => "(HL-)".to_string(),
+            IndC => "(C)".to_string(),
+            D8 => format!("${:02X}", imm),
+            D16 => format!("${:04X}", imm),
+            IndA8 => format!("(${:04X})", 0xFF00 | imm),
+            IndA16 => format!("(${:04X})", imm),
+            R8 => format!("{}", imm as u8 as i8),
+            SpR8 => format!("SP{:+}", imm as u8 as i8)
+        }
+    }
+
+    fn target(&self) -> String {
+        match self.decoded.opcode.instruction {
+            Jr(_) => format!("${:04X}", self.next.wrapping_add(self.decoded.immediate as u8 as i8 as u16)),
+            _ => format!("${:04X}", self.decoded.immediate)
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            NotZero => "NZ",
+            Zero => "Z",
+            NotCarry => "NC",
+            Carry => "C"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl<'a> fmt::Display for Disassembly<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mnemonic = self.decoded.opcode.mnemonic;
+        match self.decoded.opcode.instruction {
+            Ld(dst, src) | Add(dst, src) =>
+                write!(f, "{} {},{}", mnemonic, self.operand(dst), self.operand(src)),
+            Adc(src) | Sbc(src) =>
+                write!(f, "{} A,{}", mnemonic, self.operand(src)),
+            Push(operand) | Pop(operand) | Inc(operand) | Dec(operand) | Sub(operand) |
+            And(operand) | Xor(operand) | Or(operand) | Cp(operand) | Rlc(operand) |
+            Rrc(operand) | Rl(operand) | Rr(operand) | Sla(operand) | Sra(operand) |
+            Swap(operand) | Srl(operand) =>
+                write!(f, "{} {}", mnemonic, self.operand(operand)),
+            Bit(bit, operand) | Res(bit, operand) | Set(bit, operand) =>
+                write!(f, "{} {},{}", mnemonic, bit, self.operand(operand)),
+            Jp(None, HL) => write!(f, "{} (HL)", mnemonic),
+            Jr(None) | Jp(None, _) | Call(None) => write!(f, "{} {}", mnemonic, self.target()),
+            Jr(Some(cond)) | Jp(Some(cond), _) | Call(Some(cond)) =>
+                write!(f, "{} {},{}", mnemonic, cond, self.target()),
+            Ret(Some(cond)) => write!(f, "{} {}", mnemonic, cond),
+            Rst(vector) => write!(f, "{} {:02X}H", mnemonic, vector),
+            _ => write!(f, "{}", mnemonic)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bytes(bytes: &[u8]) -> Decoded {
+        fetch(0, false, |addr| bytes[addr as usize])
+    }
+
+    #[test]
+    fn timings_cover_every_fetch() {
+        for (code, opcode) in OPCODES.iter().enumerate() {
+            let fetched = if opcode.instruction == Stop { 1 } else { opcode.length };
+            assert!(fetched * 4 <= opcode.cycles, "{:#04x} is shorter than its fetch", code);
+            assert!(opcode.cycles_taken >= opcode.cycles, "{:#04x} is faster when taken", code);
+            let conditional = match opcode.instruction {
+                Jr(cond) | Jp(cond, _) | Call(cond) | Ret(cond) => cond.is_some(),
+                _ => false
+            };
+            assert_eq!(conditional, opcode.cycles_taken != opcode.cycles, "{:#04x}", code);
+        }
+        for opcode in CB_OPCODES.iter() {
+            assert_eq!(opcode.length, 2);
+            assert!([8, 12, 16].contains(&opcode.cycles));
+        }
+    }
+
+    #[test]
+    fn immediates_are_little_endian() {
+        let decoded = decode_bytes(&[0xC3, 0x50, 0x01]);
+        assert_eq!(decoded.opcode.instruction, Jp(None, D16));
+        assert_eq!(decoded.immediate, 0x0150);
+    }
+
+    #[test]
+    fn prefix_selects_the_cb_table() {
+        let decoded = decode_bytes(&[0xCB, 0x7E]);
+        assert_eq!(decoded.opcode.instruction, Bit(7, IndHL));
+        assert_eq!(decoded.opcode.cycles, 12);
+    }
+
+    #[test]
+    fn disassembles_operands() {
+        let decoded = decode_bytes(&[0xF0, 0x44]);
+        assert_eq!(format!("{}", Disassembly { decoded: &decoded, next: 2 }), "LDH A,($FF44)");
+        let decoded = decode_bytes(&[0x20, 0xFE]);
+        assert_eq!(format!("{}", Disassembly { decoded: &decoded, next: 2 }), "JR NZ,$0000");
+    }
+}
@@ -0,0 +1,37 @@
+//Interrupt flag (IF) and interrupt enable (IE) register addresses
+pub const IF: u16 = 0xFF0F;
+pub const IE: u16 = 0xFFFF;
+
+//Interrupt sources, declared in priority order so the discriminant is also
+//the bit position in IE/IF
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad
+}
+
+const PRIORITY: [Interrupt; 5] = [
+    Interrupt::VBlank,
+    Interrupt::LcdStat,
+    Interrupt::Timer,
+    Interrupt::Serial,
+    Interrupt::Joypad
+];
+
+impl Interrupt {
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn vector(self) -> u16 {
+        0x40 + 8 * self as u16
+    }
+
+    //Highest priority interrupt set in `pending`, a mask of IE & IF
+    pub fn highest(pending: u8) -> Option<Interrupt> {
+        PRIORITY.iter().cloned().find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
@@ -0,0 +1,69 @@
+pub const P1: u16 = 0xFF00;
+
+//Select lines, active low: bit 4 reads the d-pad, bit 5 the buttons
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+
+//Declared so the discriminant is the button's bit in `pressed`: the d-pad in
+//the low nibble and the buttons in the high one, each in P1 bit order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
+
+pub struct Joypad {
+    select: u8,
+    pressed: u8
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad { select: SELECT_DIRECTIONS | SELECT_ACTIONS, pressed: 0 }
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    //Returns whether the write pulled an input line low, which requests the
+    //joypad interrupt like a press does
+    pub fn write(&mut self, data: u8) -> bool {
+        let before = self.lines();
+        self.select = data & (SELECT_DIRECTIONS | SELECT_ACTIONS);
+        falling_edge(before, self.lines())
+    }
+
+    //Returns whether the joypad interrupt should be requested
+    pub fn press(&mut self, button: Button) -> bool {
+        let before = self.lines();
+        self.pressed |= 1 << button as u8;
+        falling_edge(before, self.lines())
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.pressed &= !(1 << button as u8);
+    }
+
+    //P10-P13, low for a pressed button in either selected group
+    fn lines(&self) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= self.pressed & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            lines |= self.pressed >> 4;
+        }
+        !lines & 0x0F
+    }
+}
+
+fn falling_edge(before: u8, after: u8) -> bool {
+    before & !after != 0
+}
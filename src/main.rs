@@ -1,44 +1,252 @@
 extern crate sdl2;
+extern crate toml;
 
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
-mod memory;
+use std::env;
+use std::path::Path;
+use std::process;
+
+mod boot;
+mod cartridge;
+mod bus;
+mod instruction;
+mod interrupt;
+mod joypad;
+mod timer;
 mod cpu;
+mod ppu;
+mod apu;
+mod timing;
 mod display;
+mod audio;
+mod input;
+
+//Flush battery-backed RAM about every five seconds of emulated time
+const SAVE_INTERVAL: u32 = 5 * 4_194_304;
+
+//Bindings are read from here when no --config is given
+const DEFAULT_CONFIG: &str = "rustboy.toml";
+
+const USAGE: &str = "usage: rustboy [--rtc-host] [--model dmg|mgb|sgb|cgb] [--boot-rom <file> | --skip-boot] [--fifo] [--sync vsync|sleep|audio] [--config <file>] [--trace] <rom>";
+
+struct Options {
+    rom: String,
+    //run the cartridge's real-time clock off the host clock instead of emulated time
+    rtc_host: bool,
+    model: boot::Model,
+    boot_rom: Option<String>,
+    skip_boot: bool,
+    //draw through the pixel FIFO rather than a line at a time
+    fifo: bool,
+    sync: timing::Sync,
+    //key and controller bindings, rustboy.toml is used if present
+    config: Option<String>,
+    //print every instruction executed once past the boot ROM
+    trace: bool
+}
 
 fn main() {
-    let mut cycle = 0;
-    let mut memory = memory::Memory::new();
+    let mut save_cycles = 0;
+    let options = parse_args();
+    let mut cartridge = match cartridge::Cartridge::from_path(&options.rom) {
+        Ok(cartridge) => cartridge,
+        Err(err) => {
+            eprintln!("{}: {}", options.rom, err);
+            process::exit(1);
+        }
+    };
+    if let Some(rtc) = cartridge.rtc() {
+        rtc.host_sync = options.rtc_host;
+    }
+    //a save that exists but can't be read is left alone rather than
+    //overwritten with blank RAM on exit
+    let save_path = Path::new(&options.rom).with_extension("sav");
+    let save_path = if !cartridge.has_save() {
+        None
+    } else if let Err(err) = cartridge.load_save(&save_path) {
+        eprintln!("{}: could not load save, saving is disabled: {}", save_path.display(), err);
+        None
+    } else {
+        Some(save_path)
+    };
+    println!("{}", cartridge.header);
+    if !cartridge.header.global_checksum_valid() {
+        eprintln!("warning: global checksum mismatch, the image may be a bad dump");
+    }
+    let boot_rom = match options.boot_rom {
+        Some(ref path) => match boot::BootRom::from_path(path, options.model) {
+            Ok(boot_rom) => Some(boot_rom),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                process::exit(1);
+            }
+        },
+        //only the DMG boot ROM is built in, other models start past it
+        None if options.model == boot::Model::Dmg && !options.skip_boot => Some(boot::BootRom::dmg()),
+        None => None
+    };
+    let header_checksum = cartridge.header.header_checksum;
+    let skip_boot = boot_rom.is_none();
+    let mut bus = bus::Bus::new(options.model, cartridge, boot_rom);
+    if options.fifo {
+        bus.ppu.set_renderer(ppu::Renderer::Fifo);
+    }
     let mut cpu:cpu::Cpu = Default::default();
-    let mut display = display::Display::new();
+    if skip_boot {
+        bus.skip_boot();
+        cpu.skip_boot(options.model, header_checksum);
+    }
+    let title = format!("rustboy - {}", bus.cartridge.header.title);
+    let mut display = display::Display::new(options.sync == timing::Sync::Vsync);
+    display.set_title(&title);
+    let texture_creator = display.texture_creator();
+    let mut screen = display::Screen::new(&texture_creator);
+    let bindings = match options.config {
+        Some(ref path) => load_bindings(path),
+        None if Path::new(DEFAULT_CONFIG).exists() => load_bindings(DEFAULT_CONFIG),
+        None => input::Bindings::defaults()
+    };
+    let mut input = input::Input::new(bindings, &display.sdl_context);
+    let mut audio = match audio::Audio::new(&display.sdl_context) {
+        Ok(audio) => Some(audio),
+        Err(err) => {
+            eprintln!("audio unavailable: {}", err);
+            None
+        }
+    };
+    let mut limiter = timing::Limiter::new();
+    let mut speed = timing::SpeedMeter::new();
+    let mut frame_cycles = 0;
     let mut running = true;
     while running {
+        //a frame takes twice as many CPU cycles in double speed
+        let budget = if cpu.double_speed { 2 * timing::FRAME_CYCLES } else { timing::FRAME_CYCLES };
+        while frame_cycles < budget {
+            if options.trace && cpu.pc > 0x100 {
+                println!("{:?}\n{}", cpu, instruction::disassemble(&bus, cpu.pc));
+            }
+            let cycles = cpu.process(&mut bus);
+            frame_cycles += cycles;
+            save_cycles += cycles;
+        }
+        frame_cycles -= budget;
+        //with the LCD off there is no new frame and the last one stays up
+        if let Some(frame) = bus.ppu.take_frame() {
+            screen.draw(frame);
+        }
+        display.present(&screen);
+        let samples = bus.apu.take_samples();
+        if let Some(ref mut audio) = audio {
+            audio.play(&samples);
+        }
         for event in display.event_pump.poll_iter() {
             match event {
                 Event::Quit {..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => {
                     running = false;
                 },
-                _ => {}
+                _ => input.handle(&event, &mut bus)
             }
         }
-        //display.update();
-        if cpu.pc > 0x100{
-            println!("{:?}", cpu);
+        input.set_rumble(bus.cartridge.rumble());
+        match options.sync {
+            timing::Sync::Vsync if display.vsync() => {}
+            timing::Sync::Audio if audio.is_some() => audio.as_ref().unwrap().wait(),
+            //without working vsync or a sound device these fall back to sleeping
+            timing::Sync::Vsync | timing::Sync::Sleep | timing::Sync::Audio => limiter.wait()
+        }
+        if let Some(percent) = speed.frame() {
+            display.set_title(&format!("{} - {:.0}%", title, percent));
         }
-        if cycle % 456 == 0 {
-            fake_screen(&mut memory);
+        if save_cycles >= SAVE_INTERVAL {
+            save_cycles = 0;
+            if let Some(ref path) = save_path {
+                if bus.cartridge.needs_save() {
+                    save(&mut bus.cartridge, path);
+                }
+            }
         }
-        cpu.process(&mut memory);
-        cpu.clock += 1;
-        cycle += 1
+    }
+    if let Some(ref path) = save_path {
+        save(&mut bus.cartridge, path);
     }
 }
 
-fn fake_screen(memory: &mut memory::Memory) {
-    if memory.contents[0xFF44] < 154 {
-        memory.contents[0xFF44] += 1;
-    } else {
-        memory.contents[0xFF44] = 0;
+fn load_bindings(path: &str) -> input::Bindings {
+    match input::Bindings::from_path(path) {
+        Ok(bindings) => bindings,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn save(cartridge: &mut cartridge::Cartridge, path: &Path) {
+    if let Err(err) = cartridge.save(path) {
+        eprintln!("{}: could not write save: {}", path.display(), err);
     }
 }
+
+fn parse_args() -> Options {
+    let mut rom = None;
+    let mut rtc_host = false;
+    let mut model = boot::Model::Dmg;
+    let mut boot_rom = None;
+    let mut skip_boot = false;
+    let mut fifo = false;
+    let mut sync = timing::Sync::Sleep;
+    let mut config = None;
+    let mut trace = false;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rtc-host" => rtc_host = true,
+            "--model" => {
+                model = match args.next().map(|name| name.parse()) {
+                    Some(Ok(model)) => model,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        usage()
+                    }
+                    None => usage()
+                }
+            }
+            "--boot-rom" => boot_rom = Some(args.next().unwrap_or_else(|| usage())),
+            "--skip-boot" => skip_boot = true,
+            "--fifo" => fifo = true,
+            "--sync" => {
+                sync = match args.next().map(|name| name.parse()) {
+                    Some(Ok(sync)) => sync,
+                    Some(Err(err)) => {
+                        eprintln!("{}", err);
+                        usage()
+                    }
+                    None => usage()
+                }
+            }
+            "--config" => config = Some(args.next().unwrap_or_else(|| usage())),
+            "--trace" => trace = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => rom = Some(arg)
+        }
+    }
+    if skip_boot && boot_rom.is_some() {
+        usage();
+    }
+    if !model.can_skip_boot() && boot_rom.is_none() {
+        eprintln!("--model cgb needs --boot-rom until CGB hardware is emulated");
+        usage();
+    }
+    match rom {
+        Some(rom) => Options { rom, rtc_host, model, boot_rom, skip_boot, fifo, sync, config, trace },
+        None => usage()
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
@@ -0,0 +1,273 @@
+use std::collections::VecDeque;
+
+use super::{Ppu, Sprite, shade, tile_color, SCREEN_WIDTH, MAP_HIGH, MAP_LOW};
+use super::{LCDC_BG_ENABLE, LCDC_BG_MAP, LCDC_SPRITE_ENABLE, LCDC_WINDOW_MAP};
+use super::{SPRITE_BEHIND_BG, SPRITE_PALETTE};
+
+//Each fetcher step before the push takes two dots
+const STEP_DOTS: u8 = 2;
+//Dots a sprite fetch stalls output for, after the background fetcher has
+//finished the tile it was working on
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Tile,
+    DataLow,
+    DataHigh,
+    //waits for the background FIFO to empty before refilling it
+    Push
+}
+
+#[derive(Clone, Copy)]
+struct SpritePixel {
+    color: u8,
+    obp1: bool,
+    behind_bg: bool
+}
+
+//Pixel transfer state for one line: the background fetcher, the two pixel
+//FIFOs and the sprites picked during OAM scan
+pub struct Fifo {
+    bg: VecDeque<u8>,
+    sprites: VecDeque<SpritePixel>,
+
+    step: Step,
+    step_dots: u8,
+    //tile column being fetched, counted from the left of the background or window
+    fetch_x: u8,
+    tile_row: usize,
+    low: u8,
+    high: u8,
+    //the first tile of every line is fetched twice, the first result discarded
+    first_fetch: bool,
+    in_window: bool,
+    window_drawn: bool,
+
+    //pixels still to drop for SCX fine scroll
+    discard: u8,
+    //next column of the line to output
+    x: u8,
+
+    line_sprites: Vec<Sprite>,
+    sprite_height: u8,
+    next_sprite: usize,
+    //dots left on a sprite fetch in progress
+    sprite_fetch: Option<u8>
+}
+
+impl Fifo {
+    pub fn new() -> Fifo {
+        Fifo {
+            bg: VecDeque::with_capacity(16),
+            sprites: VecDeque::with_capacity(8),
+            step: Step::Tile,
+            step_dots: 0,
+            fetch_x: 0,
+            tile_row: 0,
+            low: 0,
+            high: 0,
+            first_fetch: true,
+            in_window: false,
+            window_drawn: false,
+            discard: 0,
+            x: 0,
+            line_sprites: Vec::new(),
+            sprite_height: 8,
+            next_sprite: 0,
+            sprite_fetch: None
+        }
+    }
+
+    //Restarts the fetcher at the left edge of the background
+    fn restart_fetch(&mut self) {
+        self.bg.clear();
+        self.step = Step::Tile;
+        self.step_dots = 0;
+        self.fetch_x = 0;
+    }
+}
+
+impl Ppu {
+    pub(super) fn start_fifo_line(&mut self) {
+        let height = self.sprite_height();
+        let sprites = self.scan_oam(height);
+        let fifo = &mut self.fifo;
+        fifo.restart_fetch();
+        fifo.sprites.clear();
+        fifo.first_fetch = true;
+        fifo.in_window = false;
+        fifo.window_drawn = false;
+        fifo.discard = self.scx % 8;
+        fifo.x = 0;
+        fifo.line_sprites = sprites;
+        fifo.sprite_height = height;
+        fifo.next_sprite = 0;
+        fifo.sprite_fetch = None;
+    }
+
+    pub(super) fn step_fifo(&mut self) -> bool {
+        if let Some(dots) = self.fifo.sprite_fetch {
+            //the sprite fetch waits for the background fetcher to have its
+            //tile, overlapping the dot it finishes on
+            if self.fifo.step != Step::Push {
+                self.step_fetcher();
+                if self.fifo.step != Step::Push {
+                    return false;
+                }
+            }
+            if dots > 1 {
+                self.fifo.sprite_fetch = Some(dots - 1);
+            } else {
+                self.fifo.sprite_fetch = None;
+                self.fetch_sprite();
+            }
+            return false;
+        }
+        self.check_window();
+        self.step_fetcher();
+        //checked after the fetcher so a tile pushed this dot counts. This dot
+        //is the first of the sprite fetch.
+        if self.sprite_due() {
+            self.fifo.sprite_fetch = Some(SPRITE_FETCH_DOTS - 1);
+            return false;
+        }
+        self.shift_pixel()
+    }
+
+    //Whether the next sprite starts at the pixel about to be output
+    fn sprite_due(&self) -> bool {
+        let fifo = &self.fifo;
+        if self.lcdc & LCDC_SPRITE_ENABLE == 0 || fifo.bg.is_empty() || fifo.discard > 0 {
+            return false;
+        }
+        match fifo.line_sprites.get(fifo.next_sprite) {
+            Some(sprite) => sprite.x as u16 <= fifo.x as u16 + 8,
+            None => false
+        }
+    }
+
+    //Mixes the next sprite into the sprite FIFO. Pixels already there came
+    //from sprites with higher priority, so only transparent ones are replaced.
+    fn fetch_sprite(&mut self) {
+        let sprite = self.fifo.line_sprites[self.fifo.next_sprite];
+        self.fifo.next_sprite += 1;
+        //columns of a sprite hanging off the left edge are never shown
+        let skip = self.fifo.x as usize + 8 - sprite.x as usize;
+        for column in skip..8 {
+            let pixel = SpritePixel {
+                color: self.sprite_pixel(&sprite, self.fifo.sprite_height, column as u8),
+                obp1: sprite.flags & SPRITE_PALETTE != 0,
+                behind_bg: sprite.flags & SPRITE_BEHIND_BG != 0
+            };
+            let index = column - skip;
+            match self.fifo.sprites.get_mut(index) {
+                Some(existing) => {
+                    if existing.color == 0 {
+                        *existing = pixel;
+                    }
+                }
+                None => self.fifo.sprites.push_back(pixel)
+            }
+        }
+    }
+
+    //Switches the fetcher to the window once output reaches WX - 7
+    fn check_window(&mut self) {
+        if self.fifo.in_window || !self.window_active() || (self.fifo.x as u16 + 7) < self.wx as u16 {
+            return;
+        }
+        self.fifo.in_window = true;
+        self.fifo.window_drawn = true;
+        self.fifo.restart_fetch();
+        //a window at WX < 7 starts partly off the left edge
+        self.fifo.discard = 7u8.saturating_sub(self.wx);
+    }
+
+    fn step_fetcher(&mut self) {
+        if self.fifo.step == Step::Push {
+            if !self.fifo.bg.is_empty() {
+                return;
+            }
+            if self.fifo.first_fetch {
+                self.fifo.first_fetch = false;
+            } else {
+                for x in 0..8 {
+                    let color = tile_color(self.fifo.low, self.fifo.high, x);
+                    self.fifo.bg.push_back(color);
+                }
+                self.fifo.fetch_x = self.fifo.fetch_x.wrapping_add(1);
+            }
+            self.fifo.step = Step::Tile;
+            return;
+        }
+        self.fifo.step_dots += 1;
+        if self.fifo.step_dots < STEP_DOTS {
+            return;
+        }
+        self.fifo.step_dots = 0;
+        self.fifo.step = match self.fifo.step {
+            Step::Tile => {
+                self.fifo.tile_row = self.fetch_tile_row();
+                Step::DataLow
+            }
+            Step::DataLow => {
+                self.fifo.low = self.vram[self.fifo.tile_row];
+                Step::DataHigh
+            }
+            Step::DataHigh => {
+                self.fifo.high = self.vram[self.fifo.tile_row + 1];
+                Step::Push
+            }
+            Step::Push => Step::Push
+        };
+    }
+
+    //Looks up the tile for the fetcher's column, reading the scroll registers
+    //as they are now rather than at the start of the line
+    fn fetch_tile_row(&self) -> usize {
+        if self.fifo.in_window {
+            let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { MAP_HIGH } else { MAP_LOW };
+            self.tile_row(map, self.fifo.fetch_x & 31, self.window_line)
+        } else {
+            let map = if self.lcdc & LCDC_BG_MAP != 0 { MAP_HIGH } else { MAP_LOW };
+            let column = (self.scx / 8).wrapping_add(self.fifo.fetch_x) & 31;
+            self.tile_row(map, column, self.line.wrapping_add(self.scy))
+        }
+    }
+
+    //Outputs one pixel, mixing the heads of both FIFOs through the current
+    //palettes, and returns whether the line is finished
+    fn shift_pixel(&mut self) -> bool {
+        let bg = match self.fifo.bg.pop_front() {
+            Some(color) => color,
+            None => return false
+        };
+        let sprite = self.fifo.sprites.pop_front();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+        let bg_enabled = self.lcdc & LCDC_BG_ENABLE != 0;
+        let bg = if bg_enabled { bg } else { 0 };
+        let mut pixel = if bg_enabled { shade(self.bgp, bg) } else { 0 };
+        if let Some(sprite) = sprite {
+            let visible = self.lcdc & LCDC_SPRITE_ENABLE != 0 && sprite.color != 0
+                && !(sprite.behind_bg && bg != 0);
+            if visible {
+                let palette = if sprite.obp1 { self.obp1 } else { self.obp0 };
+                pixel = shade(palette, sprite.color);
+            }
+        }
+        let row = self.line as usize * SCREEN_WIDTH;
+        self.framebuffer[row + self.fifo.x as usize] = pixel;
+        self.fifo.x += 1;
+        if self.fifo.x as usize == SCREEN_WIDTH {
+            if self.fifo.window_drawn {
+                self.window_line += 1;
+            }
+            return true;
+        }
+        false
+    }
+}
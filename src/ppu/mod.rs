@@ -0,0 +1,468 @@
+use interrupt::Interrupt;
+
+mod fifo;
+
+pub const LCDC: u16 = 0xFF40;
+pub const STAT: u16 = 0xFF41;
+pub const SCY: u16 = 0xFF42;
+pub const SCX: u16 = 0xFF43;
+pub const LY: u16 = 0xFF44;
+pub const LYC: u16 = 0xFF45;
+pub const BGP: u16 = 0xFF47;
+pub const OBP0: u16 = 0xFF48;
+pub const OBP1: u16 = 0xFF49;
+pub const WY: u16 = 0xFF4A;
+pub const WX: u16 = 0xFF4B;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const VRAM_START: u16 = 0x8000;
+const VRAM_SIZE: usize = 0x2000;
+const OAM_START: u16 = 0xFE00;
+const OAM_SIZE: usize = 0xA0;
+
+//Sprites drawn on one line at most, and the OAM entries that hold them
+const SPRITES_PER_LINE: usize = 10;
+const SPRITE_COUNT: usize = 40;
+
+const LINE_DOTS: u32 = 456;
+const LINES: u8 = 154;
+const VISIBLE_LINES: u8 = 144;
+const OAM_SCAN_DOTS: u32 = 80;
+//mode 3 without sprites, window or fine scroll penalties
+const TRANSFER_DOTS: u32 = 172;
+
+const LCDC_ENABLE: u8 = 0x80;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+//tile data at 0x8000 with unsigned indices, otherwise at 0x9000 with signed ones
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_SPRITE_SIZE: u8 = 0x04;
+const LCDC_SPRITE_ENABLE: u8 = 0x02;
+const LCDC_BG_ENABLE: u8 = 0x01;
+
+//OAM attribute flags
+const SPRITE_BEHIND_BG: u8 = 0x80;
+const SPRITE_Y_FLIP: u8 = 0x40;
+const SPRITE_X_FLIP: u8 = 0x20;
+const SPRITE_PALETTE: u8 = 0x10;
+
+//Tile map offsets within VRAM
+const MAP_LOW: usize = 0x1800;
+const MAP_HIGH: usize = 0x1C00;
+
+//STAT interrupt source enables
+const STAT_HBLANK: u8 = 0x08;
+const STAT_VBLANK: u8 = 0x10;
+const STAT_OAM: u8 = 0x20;
+const STAT_LYC: u8 = 0x40;
+
+//How pixel transfer draws a line: all at once as mode 3 ends, or one pixel
+//per dot through the fetcher and FIFOs, which picks up register writes made
+//mid-line and gives mode 3 its real, variable length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Renderer {
+    Scanline,
+    Fifo
+}
+
+//The mode reported in the low two bits of STAT
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Transfer = 3
+}
+
+pub struct Ppu {
+    renderer: Renderer,
+    fifo: fifo::Fifo,
+
+    vram: Box<[u8]>,
+    oam: [u8; OAM_SIZE],
+
+    lcdc: u8,
+    //only the interrupt enables, the rest of STAT is derived
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+
+    //current line and the dot within it
+    line: u8,
+    dot: u32,
+    mode: Mode,
+
+    //the window starts on the first line equal to WY and then only advances
+    //its own line counter on lines where it is actually drawn
+    window_triggered: bool,
+    window_line: u8,
+
+    //shades 0 (white) to 3 (black), one byte per pixel
+    framebuffer: Box<[u8]>,
+    frame_ready: bool,
+
+    //the OR of all enabled STAT sources. The interrupt is only requested on
+    //its rising edge, so one source holding it high blocks the others.
+    stat_line: bool
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            renderer: Renderer::Scanline,
+            fifo: fifo::Fifo::new(),
+            vram: vec![0; VRAM_SIZE].into_boxed_slice(),
+            oam: [0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            line: 0,
+            dot: 0,
+            mode: Mode::HBlank,
+            window_triggered: false,
+            window_line: 0,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT].into_boxed_slice(),
+            frame_ready: false,
+            stat_line: false
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    //The CPU can't reach VRAM while pixel transfer is reading it
+    pub fn vram_accessible(&self) -> bool {
+        self.mode != Mode::Transfer
+    }
+
+    //or OAM from the start of OAM scan to the end of pixel transfer
+    pub fn oam_accessible(&self) -> bool {
+        self.mode != Mode::OamScan && self.mode != Mode::Transfer
+    }
+
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.vram[(addr - VRAM_START) as usize]
+    }
+
+    pub fn write_vram(&mut self, addr: u16, data: u8) {
+        self.vram[(addr - VRAM_START) as usize] = data;
+    }
+
+    pub fn read_oam(&self, addr: u16) -> u8 {
+        self.oam[(addr - OAM_START) as usize]
+    }
+
+    pub fn write_oam(&mut self, addr: u16, data: u8) {
+        self.oam[(addr - OAM_START) as usize] = data;
+    }
+
+    //The finished frame, once per frame as VBlank begins
+    pub fn take_frame(&mut self) -> Option<&[u8]> {
+        if !self.frame_ready {
+            return None;
+        }
+        self.frame_ready = false;
+        Some(&self.framebuffer)
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            LCDC => self.lcdc,
+            STAT => {
+                let coincidence = if self.coincidence() { 0x04 } else { 0x00 };
+                0x80 | self.stat | coincidence | self.mode as u8
+            }
+            SCY => self.scy,
+            SCX => self.scx,
+            LY => self.ly(),
+            LYC => self.lyc,
+            BGP => self.bgp,
+            OBP0 => self.obp0,
+            OBP1 => self.obp1,
+            WY => self.wy,
+            WX => self.wx,
+            _ => panic!("Unrecognized PPU register: {:#x}", addr)
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            LCDC => {
+                let was_enabled = self.enabled();
+                self.lcdc = data;
+                //switching the LCD off resets to the top of the frame in HBlank,
+                //frees up VRAM and OAM and leaves the screen blank
+                if was_enabled && !self.enabled() {
+                    self.line = 0;
+                    self.dot = 0;
+                    self.mode = Mode::HBlank;
+                    self.stat_line = false;
+                    self.window_triggered = false;
+                    self.window_line = 0;
+                    for pixel in self.framebuffer.iter_mut() {
+                        *pixel = 0;
+                    }
+                    self.frame_ready = true;
+                }
+            }
+            STAT => self.stat = data & 0x78,
+            SCY => self.scy = data,
+            SCX => self.scx = data,
+            //LY is read-only
+            LY => {}
+            LYC => self.lyc = data,
+            BGP => self.bgp = data,
+            OBP0 => self.obp0 = data,
+            OBP1 => self.obp1 = data,
+            WY => self.wy = data,
+            WX => self.wx = data,
+            _ => panic!("Unrecognized PPU register: {:#x}", addr)
+        }
+    }
+
+    //Advances the PPU by `dots` (4.19 MHz clocks, unaffected by double speed)
+    //and returns a mask of the interrupts to request
+    pub fn tick(&mut self, dots: u32) -> u8 {
+        let mut interrupts = 0;
+        if !self.enabled() {
+            return interrupts;
+        }
+        for _ in 0..dots {
+            self.dot += 1;
+            if self.dot == LINE_DOTS {
+                self.dot = 0;
+                self.line = (self.line + 1) % LINES;
+                if self.line == VISIBLE_LINES {
+                    interrupts |= Interrupt::VBlank.bit();
+                    self.frame_ready = true;
+                    self.window_triggered = false;
+                    self.window_line = 0;
+                }
+            }
+            let mode = if self.line >= VISIBLE_LINES {
+                Mode::VBlank
+            } else if self.dot < OAM_SCAN_DOTS {
+                Mode::OamScan
+            } else if self.mode != Mode::HBlank {
+                if self.mode == Mode::OamScan {
+                    self.start_transfer();
+                }
+                if self.step_transfer() { Mode::HBlank } else { Mode::Transfer }
+            } else {
+                Mode::HBlank
+            };
+            self.mode = mode;
+            if self.update_stat_line() {
+                interrupts |= Interrupt::LcdStat.bit();
+            }
+        }
+        interrupts
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & LCDC_ENABLE != 0
+    }
+
+    //Line 153 reports LY as 0 after its first M-cycle
+    fn ly(&self) -> u8 {
+        if self.line == LINES - 1 && self.dot >= 4 { 0 } else { self.line }
+    }
+
+    fn coincidence(&self) -> bool {
+        self.ly() == self.lyc
+    }
+
+    //Recomputes the STAT interrupt line and returns whether it just went high
+    fn update_stat_line(&mut self) -> bool {
+        let stat = self.stat;
+        let line = (stat & STAT_LYC != 0 && self.coincidence())
+            || (stat & STAT_HBLANK != 0 && self.mode == Mode::HBlank)
+            || (stat & STAT_VBLANK != 0 && self.mode == Mode::VBlank)
+            || (stat & STAT_OAM != 0 && self.mode == Mode::OamScan)
+            //the OAM source also fires as line 144 enters VBlank
+            || (stat & STAT_OAM != 0 && self.line == VISIBLE_LINES && self.dot == 0);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    fn start_transfer(&mut self) {
+        if self.line == self.wy {
+            self.window_triggered = true;
+        }
+        if self.renderer == Renderer::Fifo {
+            self.start_fifo_line();
+        }
+    }
+
+    //Runs one dot of pixel transfer and returns whether the line is finished
+    fn step_transfer(&mut self) -> bool {
+        match self.renderer {
+            //the whole line is drawn as the fixed-length mode 3 ends
+            Renderer::Scanline => {
+                if self.dot < OAM_SCAN_DOTS + TRANSFER_DOTS {
+                    return false;
+                }
+                self.render_line();
+                true
+            }
+            Renderer::Fifo => self.step_fifo()
+        }
+    }
+
+    fn render_line(&mut self) {
+        let mut colors = [0; SCREEN_WIDTH];
+        //with the background off on DMG, both it and the window are blank
+        let bg_enabled = self.lcdc & LCDC_BG_ENABLE != 0;
+        if bg_enabled {
+            self.render_background(&mut colors);
+        }
+        let row = self.line as usize * SCREEN_WIDTH;
+        for (pixel, &color) in self.framebuffer[row..row + SCREEN_WIDTH].iter_mut().zip(colors.iter()) {
+            *pixel = if bg_enabled { shade(self.bgp, color) } else { 0 };
+        }
+        if self.lcdc & LCDC_SPRITE_ENABLE != 0 {
+            self.render_sprites(&colors);
+        }
+    }
+
+    //Fills in the background and window color indices for the current line
+    fn render_background(&mut self, colors: &mut [u8; SCREEN_WIDTH]) {
+        let bg_map = if self.lcdc & LCDC_BG_MAP != 0 { MAP_HIGH } else { MAP_LOW };
+        let window_map = if self.lcdc & LCDC_WINDOW_MAP != 0 { MAP_HIGH } else { MAP_LOW };
+        let window = self.window_active();
+        let mut window_drawn = false;
+        for x in 0..SCREEN_WIDTH as u8 {
+            //WX is the window's left edge plus 7
+            colors[x as usize] = if window && x as u16 + 7 >= self.wx as u16 {
+                window_drawn = true;
+                self.tile_pixel(window_map, x + 7 - self.wx, self.window_line)
+            } else {
+                self.tile_pixel(bg_map, x.wrapping_add(self.scx), self.line.wrapping_add(self.scy))
+            };
+        }
+        if window_drawn {
+            self.window_line += 1;
+        }
+    }
+
+    fn window_active(&self) -> bool {
+        self.lcdc & LCDC_WINDOW_ENABLE != 0 && self.window_triggered && self.wx <= 166
+    }
+
+    //Draws the line's sprites over the background, given its color indices
+    fn render_sprites(&mut self, bg_colors: &[u8; SCREEN_WIDTH]) {
+        let height = self.sprite_height();
+        let sprites = self.scan_oam(height);
+        let row = self.line as usize * SCREEN_WIDTH;
+        for (x, &bg_color) in bg_colors.iter().enumerate() {
+            //X is the sprite's right edge, so on screen it spans x..x+8 minus 8
+            let pixel = sprites.iter().filter_map(|sprite| {
+                let column = x + 8;
+                if column < sprite.x as usize || column >= sprite.x as usize + 8 {
+                    return None;
+                }
+                let color = self.sprite_pixel(sprite, height, (column - sprite.x as usize) as u8);
+                if color == 0 { None } else { Some((sprite, color)) }
+            }).next();
+            if let Some((sprite, color)) = pixel {
+                if sprite.flags & SPRITE_BEHIND_BG != 0 && bg_color != 0 {
+                    continue;
+                }
+                let palette = if sprite.flags & SPRITE_PALETTE != 0 { self.obp1 } else { self.obp0 };
+                self.framebuffer[row + x] = shade(palette, color);
+            }
+        }
+    }
+
+    fn sprite_height(&self) -> u8 {
+        if self.lcdc & LCDC_SPRITE_SIZE != 0 { 16 } else { 8 }
+    }
+
+    //The first ten sprites in OAM order whose rows cover the current line,
+    //sorted by priority. On DMG the sprite with the lowest X wins, then the
+    //earliest in OAM, which the stable sort keeps on ties.
+    fn scan_oam(&self, height: u8) -> Vec<Sprite> {
+        let line = self.line as u16 + 16;
+        let mut sprites: Vec<Sprite> = (0..SPRITE_COUNT)
+            .map(|index| Sprite::from_oam(&self.oam[index * 4..index * 4 + 4]))
+            .filter(|sprite| line >= sprite.y as u16 && line < sprite.y as u16 + height as u16)
+            .take(SPRITES_PER_LINE)
+            .collect();
+        sprites.sort_by_key(|sprite| sprite.x);
+        sprites
+    }
+
+    //Color index of column `x` of `sprite` on the current line
+    fn sprite_pixel(&self, sprite: &Sprite, height: u8, x: u8) -> u8 {
+        let mut y = self.line + 16 - sprite.y;
+        if sprite.flags & SPRITE_Y_FLIP != 0 {
+            y = height - 1 - y;
+        }
+        let x = if sprite.flags & SPRITE_X_FLIP != 0 { 7 - x } else { x };
+        //tall sprites ignore the low bit of the tile index
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        let row = tile as usize * 16 + y as usize * 2;
+        tile_color(self.vram[row], self.vram[row + 1], x)
+    }
+
+    //Color index 0-3 at (x, y) of the 256x256 plane described by the tile map at `map`
+    fn tile_pixel(&self, map: usize, x: u8, y: u8) -> u8 {
+        let row = self.tile_row(map, x / 8, y);
+        tile_color(self.vram[row], self.vram[row + 1], x % 8)
+    }
+
+    //VRAM offset of the tile row at line `y` and tile column `column` of a tile map
+    fn tile_row(&self, map: usize, column: u8, y: u8) -> usize {
+        let index = self.vram[map + (y as usize / 8) * 32 + column as usize];
+        let tile = if self.lcdc & LCDC_TILE_DATA != 0 {
+            index as usize * 16
+        } else {
+            (0x1000 + index as i8 as isize * 16) as usize
+        };
+        tile + (y as usize % 8) * 2
+    }
+}
+
+//An OAM entry. Y and X are offset by 16 and 8 so sprites can sit partly off screen.
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile: u8,
+    flags: u8
+}
+
+impl Sprite {
+    fn from_oam(entry: &[u8]) -> Sprite {
+        Sprite { y: entry[0], x: entry[1], tile: entry[2], flags: entry[3] }
+    }
+}
+
+//Color index of column `x` in a tile row, given its two bitplanes
+fn tile_color(low: u8, high: u8, x: u8) -> u8 {
+    let bit = 7 - x;
+    ((high >> bit) & 0x01) << 1 | ((low >> bit) & 0x01)
+}
+
+//Maps a color index through a palette register
+fn shade(palette: u8, color: u8) -> u8 {
+    (palette >> (color * 2)) & 0x03
+}
@@ -0,0 +1,222 @@
+pub const DIV: u16 = 0xFF04;
+pub const TIMA: u16 = 0xFF05;
+pub const TMA: u16 = 0xFF06;
+pub const TAC: u16 = 0xFF07;
+
+//Bit of the internal counter whose falling edge increments TIMA, indexed by
+//the TAC clock select: 4096 Hz, 262144 Hz, 65536 Hz and 16384 Hz
+const TAC_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+
+pub struct Timer {
+    //16-bit internal divider, of which DIV is the upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+
+    //TIMA overflowed during the last M-cycle and reads 0 until it is
+    //reloaded from TMA at the end of the next one
+    overflowed: bool,
+    //TIMA was reloaded from TMA during the current M-cycle
+    reloaded: bool,
+
+    //T-cycles not yet making up a whole M-cycle
+    remainder: u32
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloaded: false,
+            remainder: 0
+        }
+    }
+
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    //Sets the internal counter without the edge detection a DIV write does
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => self.tac | 0xF8,
+            _ => panic!("Unrecognized timer register: {:#x}", addr)
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            //resetting the counter is a falling edge if the selected bit was set
+            DIV => {
+                let before = self.input();
+                self.counter = 0;
+                self.detect_edge(before);
+            }
+            //a write while the reload is pending cancels it, and a write in
+            //the cycle the reload happens is lost
+            TIMA => {
+                if !self.reloaded {
+                    self.tima = data;
+                    self.overflowed = false;
+                }
+            }
+            //a write in the cycle TMA is copied also lands in TIMA
+            TMA => {
+                self.tma = data;
+                if self.reloaded {
+                    self.tima = data;
+                }
+            }
+            //disabling the timer or switching to a clear bit is a falling edge
+            TAC => {
+                let before = self.input();
+                self.tac = data & 0x07;
+                self.detect_edge(before);
+            }
+            _ => panic!("Unrecognized timer register: {:#x}", addr)
+        }
+    }
+
+    //Advances the timer by `cycles` T-cycles and returns whether the timer
+    //interrupt should be requested
+    pub fn tick(&mut self, cycles: u32) -> bool {
+        let mut interrupt = false;
+        self.remainder += cycles;
+        while self.remainder >= 4 {
+            self.remainder -= 4;
+            interrupt |= self.step();
+        }
+        interrupt
+    }
+
+    fn step(&mut self) -> bool {
+        let mut interrupt = false;
+        self.reloaded = false;
+        if self.overflowed {
+            self.overflowed = false;
+            self.reloaded = true;
+            self.tima = self.tma;
+            interrupt = true;
+        }
+        let before = self.input();
+        self.counter = self.counter.wrapping_add(4);
+        self.detect_edge(before);
+        interrupt
+    }
+
+    //The selected counter bit ANDed with the enable bit, which clocks TIMA
+    fn input(&self) -> bool {
+        self.tac & 0x04 != 0 && self.counter & TAC_BITS[(self.tac & 0x03) as usize] != 0
+    }
+
+    fn detect_edge(&mut self, before: bool) {
+        if before && !self.input() {
+            self.increment();
+        }
+    }
+
+    fn increment(&mut self) {
+        if self.tima == 0xFF {
+            self.tima = 0;
+            self.overflowed = true;
+        } else {
+            self.tima += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Enabled at 262144 Hz, where TIMA counts on counter bit 3 falling
+    fn enabled(counter: u16) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0x05);
+        timer.set_counter(counter);
+        timer
+    }
+
+    //Ticks TIMA from 0xFF over the edge, leaving it in the overflow cycle
+    fn overflow(timer: &mut Timer) {
+        timer.write(TMA, 0x42);
+        timer.write(TIMA, 0xFF);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x00);
+    }
+
+    #[test]
+    fn tima_counts_at_the_selected_rate() {
+        let mut timer = enabled(0);
+        timer.tick(64);
+        assert_eq!(timer.read(TIMA), 4);
+        timer.write(TAC, 0x04);
+        timer.tick(1024);
+        assert_eq!(timer.read(TIMA), 5);
+    }
+
+    #[test]
+    fn div_write_increments_tima_when_the_selected_bit_is_set() {
+        let mut timer = enabled(0x0008);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 1);
+        assert_eq!(timer.read(DIV), 0);
+
+        let mut timer = enabled(0x0010);
+        timer.write(DIV, 0);
+        assert_eq!(timer.read(TIMA), 0);
+    }
+
+    #[test]
+    fn tac_write_that_drops_the_input_increments_tima() {
+        //switching to bit 9, which is clear
+        let mut timer = enabled(0x0008);
+        timer.write(TAC, 0x04);
+        assert_eq!(timer.read(TIMA), 1);
+        //disabling with the selected bit set
+        let mut timer = enabled(0x0008);
+        timer.write(TAC, 0x01);
+        assert_eq!(timer.read(TIMA), 1);
+    }
+
+    #[test]
+    fn overflow_reloads_tma_one_cycle_late() {
+        let mut timer = enabled(0x000C);
+        overflow(&mut timer);
+        assert!(timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    fn tima_write_in_overflow_cycle_cancels_reload() {
+        let mut timer = enabled(0x000C);
+        overflow(&mut timer);
+        timer.write(TIMA, 0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.read(TIMA), 0x10);
+    }
+
+    #[test]
+    fn writes_in_reload_cycle_follow_tma() {
+        let mut timer = enabled(0x000C);
+        overflow(&mut timer);
+        assert!(timer.tick(4));
+        //a TIMA write is lost, a TMA write lands in TIMA too
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x42);
+        timer.write(TMA, 0x24);
+        assert_eq!(timer.read(TIMA), 0x24);
+    }
+}
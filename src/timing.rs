@@ -0,0 +1,86 @@
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+//T-cycles in a frame of 154 lines at single speed
+pub const FRAME_CYCLES: u32 = 70224;
+const CLOCK_HZ: u64 = 4_194_304;
+//about 16.74 ms, or 59.7275 frames a second
+const FRAME_NANOS: u64 = 1_000_000_000 * FRAME_CYCLES as u64 / CLOCK_HZ;
+//frames the limiter will fall behind by before giving up on catching up
+const MAX_LAG: u32 = 4;
+
+//What keeps emulation running at real-time speed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sync {
+    //presenting blocks until the host display's next refresh, so speed
+    //follows the monitor's rate rather than exactly 59.73 Hz
+    Vsync,
+    //sleeps out the rest of each frame
+    Sleep,
+    //waits for queued sound to play, so speed follows the sound card's clock
+    Audio
+}
+
+impl FromStr for Sync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Sync, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "vsync" => Ok(Sync::Vsync),
+            "sleep" => Ok(Sync::Sleep),
+            "audio" => Ok(Sync::Audio),
+            _ => Err(format!("unknown sync mode: {}", s))
+        }
+    }
+}
+
+//Paces frames to the Game Boy's refresh rate by sleeping
+pub struct Limiter {
+    frame: Duration,
+    next: Instant
+}
+
+impl Limiter {
+    pub fn new() -> Limiter {
+        Limiter { frame: Duration::from_nanos(FRAME_NANOS), next: Instant::now() }
+    }
+
+    //Waits until the current frame's time slot is over. Running slow for a
+    //while doesn't lead to a burst of fast frames afterwards.
+    pub fn wait(&mut self) {
+        self.next += self.frame;
+        let now = Instant::now();
+        if self.next > now {
+            thread::sleep(self.next - now);
+        } else if now - self.next > self.frame * MAX_LAG {
+            self.next = now;
+        }
+    }
+}
+
+//Emulated speed as a percentage of real hardware, measured about once a second
+pub struct SpeedMeter {
+    start: Instant,
+    frames: u64
+}
+
+impl SpeedMeter {
+    pub fn new() -> SpeedMeter {
+        SpeedMeter { start: Instant::now(), frames: 0 }
+    }
+
+    //Counts a frame, returning the speed when a new measurement is ready
+    pub fn frame(&mut self) -> Option<f64> {
+        self.frames += 1;
+        let elapsed = self.start.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let emulated = Duration::from_nanos(FRAME_NANOS * self.frames);
+        let percent = emulated.as_secs_f64() / elapsed.as_secs_f64() * 100.0;
+        self.start = Instant::now();
+        self.frames = 0;
+        Some(percent)
+    }
+}